@@ -0,0 +1,16 @@
+create table reminder_leads (
+    critter bigint not null primary key references critters(id) on delete cascade,
+    -- minutes before a shift at which the critter gets reminded
+    leads integer[] not null
+);
+
+create table reminders (
+    shift bigint not null references shifts(id) on delete cascade,
+    -- critters may not have linked their telegram account (yet)
+    critter bigint not null,
+    lead integer not null,
+
+    primary key(shift, critter, lead)
+);
+
+alter table shifts drop column notified;
@@ -0,0 +1,10 @@
+-- critters show up in shifts long before they link their telegram account, if they ever do
+alter table assignments drop constraint assignments_critter_fkey;
+
+alter table assignments drop constraint assignments_shift_fkey;
+alter table assignments add constraint assignments_shift_fkey
+    foreign key (shift) references shifts(id) on delete cascade;
+
+insert into assignments (shift, critter)
+select s.id, (c->>2)::bigint from shifts s, json_array_elements(s.meta->'critters') c
+on conflict do nothing;
@@ -0,0 +1,14 @@
+create table manager_assignments (
+    shift bigint not null references shifts(id) on delete cascade,
+    -- managers may not have linked their telegram account (yet)
+    critter bigint not null,
+
+    informed boolean not null default false,
+
+    primary key(shift, critter)
+);
+create index on manager_assignments(critter);
+
+insert into manager_assignments (shift, critter)
+select s.id, (m->>1)::bigint from shifts s, json_array_elements(s.meta->'managers') m
+on conflict do nothing;
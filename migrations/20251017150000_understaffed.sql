@@ -0,0 +1,2 @@
+-- whether staff has been alerted about the shift lacking critters
+alter table shifts add column understaffed boolean not null default false;
@@ -0,0 +1,7 @@
+-- posts of unfilled shifts in the open shift channel, shifts may already be gone when the post is finalized
+create table open_shift_posts (
+    shift bigint not null primary key,
+    chat bigint not null,
+    message integer not null,
+    content text not null
+);
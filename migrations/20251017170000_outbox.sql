@@ -0,0 +1,13 @@
+create table outbox (
+    id bigserial primary key,
+    -- deduplicates events which get enqueued more than once
+    key text not null unique,
+    event jsonb not null,
+    status text not null default 'pending'
+        check (status in ('pending', 'sent', 'failed', 'blocked', 'skipped')),
+    attempts integer not null default 0,
+    next_attempt timestamp not null default (now() at time zone 'utc'),
+    error text,
+    created timestamp not null default (now() at time zone 'utc')
+);
+create index on outbox(next_attempt) where status = 'pending';
@@ -0,0 +1,9 @@
+-- events are delivered strictly in order per recipient
+alter table outbox add column target text;
+update outbox set target = coalesce(
+    'critter-' || (event->>'uid'),
+    'critter-' || (event->'to'->>'Critter'),
+    'chat-' || (event->'to'->>'Chat')
+);
+alter table outbox alter column target set not null;
+create index on outbox(target, id) where status = 'pending';
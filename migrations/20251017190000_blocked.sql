@@ -0,0 +1,2 @@
+-- set once telegram tells us the critter blocked the bot, cleared as soon as they write to it again
+alter table critters add column blocked_at timestamp;
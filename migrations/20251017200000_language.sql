@@ -0,0 +1,3 @@
+-- language picked via /language, takes precedence over the one reported by telegram
+alter table critters add column language text;
+alter table critters add column tg_language text;
@@ -0,0 +1,2 @@
+-- secret part of the url of a critters calendar feed
+alter table critters add column calendar_token text unique;
@@ -0,0 +1,2 @@
+-- revision of the calendar entries of a shift, bumped whenever its time changes
+alter table shifts add column sequence int not null default 0;
@@ -0,0 +1,9 @@
+-- broadcasts of admins, recipients are resolved when previewing so the confirmed count is the one sent to
+create table broadcasts (
+    id bigserial primary key,
+    admin bigint not null,
+    text text not null,
+    recipients bigint[] not null,
+    sent_at timestamp,
+    created timestamp not null default (now() at time zone 'utc')
+);
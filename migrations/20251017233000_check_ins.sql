@@ -0,0 +1,8 @@
+-- answers of critters to the buttons of their shift reminders, only the latest one is kept
+create table check_ins (
+    shift bigint not null references shifts(id) on delete cascade,
+    critter bigint not null,
+    status text not null check (status in ('on_my_way', 'checked_in', 'cant_make_it')),
+    at timestamp not null default (now() at time zone 'utc'),
+    primary key(shift, critter)
+);
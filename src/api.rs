@@ -5,12 +5,22 @@ use reqwest::{
     Client, ClientBuilder, StatusCode, Url,
     header::{AUTHORIZATION, HeaderMap, HeaderValue},
 };
-use std::{borrow::Cow, iter::repeat, sync::Arc};
-use tracing::{debug, error};
+use std::{
+    borrow::Cow,
+    iter::repeat,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use tracing::{debug, error, warn};
 use uuid::Uuid;
 
 use crate::events::Shift;
 
+/// Whether we already warned about shifts without managers, older APIs never send them
+static UNMANAGED_WARNED: AtomicBool = AtomicBool::new(false);
+
 #[derive(Clone)]
 pub struct Api {
     api_url: Arc<Url>,
@@ -32,6 +42,20 @@ impl Api {
         })
     }
 
+    /// Host of the crittersystem, used to scope identifiers to it
+    pub fn host(&self) -> &str {
+        self.api_url.host_str().unwrap_or("critter")
+    }
+
+    /// Link to a shift in the critter web interface
+    pub fn shift_url(&self, id: i64) -> eyre::Result<Url> {
+        let mut url = self.api_url.join("shifts")?;
+        url.query_pairs_mut()
+            .append_pair("action", "view")
+            .append_pair("shift_id", &id.to_string());
+        Ok(url)
+    }
+
     #[tracing::instrument(name = "api_verify", skip(self))]
     pub async fn verify(
         &self,
@@ -88,6 +112,22 @@ impl Api {
             .json::<ApiShifts>()
             .await?;
 
+        let unmanaged = shifts
+            .shifts
+            .iter()
+            .filter(|shift| shift.managers.is_none())
+            .count();
+        if unmanaged > 0 {
+            let total = shifts.shifts.len();
+            if UNMANAGED_WARNED.swap(true, Ordering::Relaxed) {
+                debug!("{unmanaged} of {total} shifts lack the `managers` field");
+            } else {
+                warn!(
+                    "{unmanaged} of {total} shifts lack the `managers` field, their managers won't be notified"
+                );
+            }
+        }
+
         Ok(shifts
             .shifts
             .into_iter()
@@ -111,8 +151,12 @@ impl Api {
                             })
                     })
                     .collect(),
-                // FIXME: wait for api changes!
-                managers: vec![],
+                managers: shift
+                    .managers
+                    .into_iter()
+                    .flatten()
+                    .map(|manager| (manager.user_name, manager.user_id))
+                    .collect(),
                 req: shift.required as usize,
                 ppe: shift.eligibility.needs_cert,
                 tz,
@@ -151,6 +195,14 @@ struct ApiShift {
     required: i32,
     eligibility: ApiEligibility,
     assignments: Vec<ApiAssignment>,
+    /// Not sent by every version of the API, in which case the shift has no managers
+    managers: Option<Vec<ApiManager>>,
+}
+
+#[derive(serde::Deserialize)]
+struct ApiManager {
+    user_id: i64,
+    user_name: Arc<str>,
 }
 
 #[derive(serde::Deserialize)]
@@ -0,0 +1,139 @@
+use chrono::Utc;
+use color_eyre::eyre;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+use tracing::{error, trace};
+
+use crate::{State, db::OpenShiftPost, events::Shift, i18n::Lang, render};
+
+/// The channel is read by everyone, so posts stick to a single language
+const LANG: Lang = Lang::En;
+
+/// Keeps the posts of unfilled shifts in the open shift channel in line with the current shifts.
+/// Posts of shifts which got filled, started or deleted are finalized and no longer tracked.
+#[tracing::instrument(name = "board_sync", skip_all)]
+pub async fn sync_board(
+    state: &State,
+    chat: ChatId,
+    shifts: &[Shift],
+    deleted: &[Shift],
+) -> eyre::Result<()> {
+    let t = LANG.text();
+    let posts = state.db.open_shift_posts().await?;
+    let now = Utc::now();
+
+    for shift in shifts {
+        let until = shift.start.signed_duration_since(now).num_minutes();
+        let open = shift.critters.len() < shift.req
+            && (0..=i64::from(state.open_shifts_window)).contains(&until);
+        let res = match (posts.get(&shift.id), open) {
+            (None, true) => post(state, chat, shift).await,
+            (Some(post), true) => update(state, shift, post).await,
+            (Some(post), false) if until < 0 => finalize(state, shift, post, t.started).await,
+            (Some(post), false) => finalize(state, shift, post, t.filled).await,
+            (None, false) => Ok(()),
+        };
+        if let Err(err) = res {
+            error!("Failed to sync open shift post of {}: {err}", shift.id);
+        }
+    }
+    for shift in deleted {
+        let Some(post) = posts.get(&shift.id) else {
+            continue;
+        };
+        if let Err(err) = finalize(state, shift, post, t.closed).await {
+            error!("Failed to sync open shift post of {}: {err}", shift.id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn post(state: &State, chat: ChatId, shift: &Shift) -> eyre::Result<()> {
+    trace!(shift = shift.id, "posting open shift");
+    let content = render::open_shift(shift, LANG.text().open_shift, LANG)?;
+    let markup = sign_up(state, shift)?;
+    let msg = state
+        .sender
+        .request(chat, || {
+            state
+                .bot
+                .send_message(chat, &content)
+                .parse_mode(render::PARSE_MODE)
+                .reply_markup(markup.clone())
+                .send()
+        })
+        .await?;
+    state
+        .db
+        .set_open_shift_post(
+            shift.id,
+            &OpenShiftPost {
+                chat,
+                message: msg.id,
+                content,
+            },
+        )
+        .await
+}
+
+async fn update(state: &State, shift: &Shift, post: &OpenShiftPost) -> eyre::Result<()> {
+    let content = render::open_shift(shift, LANG.text().open_shift, LANG)?;
+    if content == post.content {
+        return Ok(());
+    }
+    trace!(shift = shift.id, "updating open shift");
+    let markup = sign_up(state, shift)?;
+    state
+        .sender
+        .request(post.chat, || {
+            state
+                .bot
+                .edit_message_text(post.chat, post.message, &content)
+                .parse_mode(render::PARSE_MODE)
+                .reply_markup(markup.clone())
+                .send()
+        })
+        .await?;
+    state
+        .db
+        .set_open_shift_post(
+            shift.id,
+            &OpenShiftPost {
+                chat: post.chat,
+                message: post.message,
+                content,
+            },
+        )
+        .await
+}
+
+async fn finalize(
+    state: &State,
+    shift: &Shift,
+    post: &OpenShiftPost,
+    status: &str,
+) -> eyre::Result<()> {
+    trace!(shift = shift.id, status, "finalizing open shift");
+    let content = render::open_shift(shift, status, LANG)?;
+    state
+        .sender
+        .request(post.chat, || {
+            state
+                .bot
+                .edit_message_text(post.chat, post.message, &content)
+                .parse_mode(render::PARSE_MODE)
+                .send()
+        })
+        .await?;
+    state.db.delete_open_shift_post(shift.id).await
+}
+
+fn sign_up(state: &State, shift: &Shift) -> eyre::Result<InlineKeyboardMarkup> {
+    Ok(InlineKeyboardMarkup::new([[InlineKeyboardButton::url(
+        LANG.text().sign_up,
+        state.api.shift_url(shift.id)?,
+    )]]))
+}
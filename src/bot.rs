@@ -1,42 +1,793 @@
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 use color_eyre::eyre;
-use reqwest::StatusCode;
-use teloxide::{dispatching::dialogue::GetChatId, prelude::*};
-use tracing::error;
+use std::fmt::Write;
+use teloxide::{
+    dispatching::dialogue::GetChatId,
+    prelude::*,
+    types::{BotCommand, InlineKeyboardButton, InlineKeyboardMarkup, MessageId, User},
+    update_listeners::webhooks,
+    utils::{
+        command::BotCommands,
+        html::{bold, escape},
+    },
+};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::State;
+use crate::{
+    State, broadcast,
+    db::{CheckIn, Database, LEAD_OPTIONS},
+    events::{Event, Shift},
+    i18n::{Lang, fill},
+    metrics,
+    render::{self, Relative, ShiftLine, Span},
+};
 
-async fn default(State { api, bot, db, .. }: State, msg: Message) -> eyre::Result<()> {
-    let (Some(chat_id), Some(uname), Some(text)) = (msg.chat_id(), msg.chat.username(), msg.text())
-    else {
-        return Ok(());
-    };
-    if db.check_if_present(chat_id).await?.is_some() {
-        // No futher action required, the user is already verified
-        return Ok(());
+#[derive(BotCommands, Clone)]
+#[command(
+    rename_rule = "lowercase",
+    description = "These commands are supported:"
+)]
+pub enum Command {
+    #[command(
+        description = "link your critter account, use the link provided by the web interface"
+    )]
+    Start(String),
+    #[command(description = "show this help")]
+    Help,
+    #[command(description = "list your upcoming shifts")]
+    MyShifts,
+    #[command(description = "change your notification settings")]
+    Settings,
+    #[command(description = "unlink your critter account from this chat")]
+    Unlink,
+    #[command(description = "change the language of the bot")]
+    Language,
+    #[command(description = "get a calendar feed of your shifts")]
+    Calendar,
+    #[command(description = "see who checked in for a shift you manage")]
+    Roster(String),
+}
+
+/// Command menu in the given language
+fn commands(lang: Lang) -> Vec<BotCommand> {
+    let t = lang.text();
+    Command::bot_commands()
+        .into_iter()
+        .map(|cmd| {
+            let description = match cmd.command.trim_start_matches('/') {
+                "start" => t.cmd_start,
+                "help" => t.cmd_help,
+                "myshifts" => t.cmd_myshifts,
+                "settings" => t.cmd_settings,
+                "unlink" => t.cmd_unlink,
+                "language" => t.cmd_language,
+                "calendar" => t.cmd_calendar,
+                "roster" => t.cmd_roster,
+                _ => return cmd,
+            };
+            BotCommand::new(cmd.command, description)
+        })
+        .collect()
+}
+
+fn help(lang: Lang) -> String {
+    let mut text = lang.text().help.to_string();
+    for cmd in commands(lang) {
+        let _ = write!(text, "\n{} — {}", cmd.command, cmd.description);
     }
+    text
+}
 
-    if !text.starts_with("/start ") {
-        bot.send_message(chat_id, "Unknown command provided.\nTry logging in via the web interface https://critter.eurofurence.org/").await?;
+/// Language to answer a chat in, the one picked via `/language` wins over the one of the telegram client
+async fn lang(db: &Database, chat_id: ChatId, user: Option<&User>) -> eyre::Result<Lang> {
+    Ok(db
+        .language(chat_id)
+        .await?
+        .or_else(|| {
+            user.and_then(|u| u.language_code.as_deref())
+                .and_then(Lang::from_code)
+        })
+        .unwrap_or_default())
+}
+
+/// Commands reserved for admins, these are not advertised in the telegram command menu
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "Admin commands:")]
+pub enum AdminCommand {
+    #[command(description = "show bot statistics")]
+    Stats,
+    #[command(description = "force a resync of the given date, or of every day if none is given")]
+    Resync(String),
+    #[command(description = "look up a critter by their id")]
+    Whois(String),
+    #[command(description = "send a message to every linked critter or only some of them")]
+    Broadcast(String),
+}
+
+async fn command(state: State, msg: Message, cmd: Command) -> eyre::Result<()> {
+    let chat_id = msg.chat.id;
+    let lang = lang(&state.db, chat_id, msg.from.as_ref()).await?;
+    let t = lang.text();
+    match cmd {
+        Command::Start(token) => start(&state, &msg, token.trim(), lang).await?,
+        Command::Help => {
+            let mut text = help(lang);
+            if admin(&state, msg.from.as_ref()).await?.is_some() {
+                write!(text, "\n\n{}", AdminCommand::descriptions())?;
+            }
+            state.bot.send_message(chat_id, text).await?;
+        }
+        Command::MyShifts => {
+            let Some(uid) = linked(&state, chat_id, lang).await? else {
+                return Ok(());
+            };
+            let (text, markup) = my_shifts(&state, uid, 0, lang).await?;
+            let req = state
+                .bot
+                .send_message(chat_id, text)
+                .parse_mode(render::PARSE_MODE);
+            match markup {
+                Some(markup) => req.reply_markup(markup).await?,
+                None => req.await?,
+            };
+        }
+        Command::Unlink => {
+            if state.db.unlink(chat_id).await?.is_none() {
+                state.bot.send_message(chat_id, t.not_linked).await?;
+                return Ok(());
+            }
+            state.bot.send_message(chat_id, t.unlinked).await?;
+        }
+        Command::Settings => {
+            let Some(uid) = linked(&state, chat_id, lang).await? else {
+                return Ok(());
+            };
+            let (text, markup) = settings(&state, uid, lang).await?;
+            state
+                .bot
+                .send_message(chat_id, text)
+                .reply_markup(markup)
+                .await?;
+        }
+        Command::Language => {
+            if linked(&state, chat_id, lang).await?.is_none() {
+                return Ok(());
+            }
+            state
+                .bot
+                .send_message(chat_id, t.language_prompt)
+                .reply_markup(languages(lang))
+                .await?;
+        }
+        Command::Calendar => {
+            let Some(uid) = linked(&state, chat_id, lang).await? else {
+                return Ok(());
+            };
+            let Some(base) = &state.calendar_url else {
+                state
+                    .bot
+                    .send_message(chat_id, t.calendar_unavailable)
+                    .await?;
+                return Ok(());
+            };
+            let token = state.db.calendar_token(uid).await?;
+            let url = base.join(&format!("calendar/{token}.ics"))?;
+            let text = fill(t.calendar, &[("url", &url)]);
+            state.bot.send_message(chat_id, text).await?;
+        }
+        Command::Roster(shift) => {
+            let Some(uid) = linked(&state, chat_id, lang).await? else {
+                return Ok(());
+            };
+            let Ok(id) = shift.trim().parse::<i64>() else {
+                state.bot.send_message(chat_id, t.roster_usage).await?;
+                return Ok(());
+            };
+            let Some(shift) = state.db.shift(id).await? else {
+                state.bot.send_message(chat_id, t.unknown_shift).await?;
+                return Ok(());
+            };
+            if !shift.managers.iter().any(|m| m.1 == uid)
+                && admin(&state, msg.from.as_ref()).await?.is_none()
+            {
+                state.bot.send_message(chat_id, t.not_a_manager).await?;
+                return Ok(());
+            }
+            let answers = state.db.check_ins(id).await?;
+            state
+                .bot
+                .send_message(chat_id, render::check_ins(&shift, &answers, lang)?)
+                .parse_mode(render::PARSE_MODE)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Looks up the critter linked to a chat, telling the user how to link their account if there is none
+async fn linked(
+    State { bot, db, .. }: &State,
+    chat_id: ChatId,
+    lang: Lang,
+) -> eyre::Result<Option<i64>> {
+    let t = lang.text();
+    let uid = db.check_if_present(chat_id).await?;
+    if uid.is_none() {
+        bot.send_message(chat_id, format!("{}\n{}", t.not_linked_yet, t.login_hint))
+            .await?;
+    }
+    Ok(uid)
+}
+
+async fn start(
+    State { api, bot, db, .. }: &State,
+    msg: &Message,
+    token: &str,
+    lang: Lang,
+) -> eyre::Result<()> {
+    let t = lang.text();
+    let chat_id = msg.chat.id;
+    if db.check_if_present(chat_id).await?.is_some() {
+        bot.send_message(chat_id, t.already_linked).await?;
         return Ok(());
     }
-    let token = text[7..].trim();
+    let Some(uname) = msg.chat.username() else {
+        bot.send_message(chat_id, t.need_username).await?;
+        return Ok(());
+    };
     let Ok(token) = Uuid::parse_str(token) else {
-        bot.send_message(chat_id, "Malformed token provided.\nTry logging in via the web interface https://critter.eurofurence.org/").await?;
+        bot.send_message(chat_id, format!("{}\n{}", t.malformed_token, t.login_hint))
+            .await?;
         return Ok(());
     };
-    let uid = match api.verify(token, uname.into()).await? {
+    let uid = match api
+        .verify(token, uname.into())
+        .await
+        .inspect_err(|_| metrics::API_ERRORS.with_label_values(&["verify"]).inc())?
+    {
         Ok(uid) => uid,
         Err(reason) => {
             bot.send_message(chat_id, reason).await?;
             return Ok(());
         }
     };
-    db.register(uid, chat_id).await?;
+    let tg_language = msg.from.as_ref().and_then(|u| u.language_code.as_deref());
+    if let Some(old) = db.register(uid, chat_id, tg_language).await? {
+        let res = bot.send_message(old, t.linked_elsewhere).await;
+        if let Err(err) = res {
+            warn!("Failed to inform previously linked chat: {err}");
+        }
+    }
 
-    bot.send_message(chat_id, "Your account has been linked successfully!\nFrom now on you will receive notification on any of your upcoming shifts.\nThank you for helping us out!")
+    bot.send_message(chat_id, t.linked).await?;
+
+    Ok(())
+}
+
+/// Critter id of the sender if they are an admin, admins are only recognized once they linked their account
+async fn admin(state: &State, user: Option<&User>) -> eyre::Result<Option<i64>> {
+    let Some(user) = user else {
+        return Ok(None);
+    };
+    let Some(uid) = state.db.check_if_present(ChatId::from(user.id)).await? else {
+        return Ok(None);
+    };
+    let admin = state.admin_critters.contains(&uid) || state.admin_users.contains(&user.id);
+    Ok(admin.then_some(uid))
+}
+
+async fn admin_command(state: State, msg: Message, cmd: AdminCommand) -> eyre::Result<()> {
+    let chat_id = msg.chat.id;
+    let Some(admin_uid) = admin(&state, msg.from.as_ref()).await? else {
+        let lang = lang(&state.db, chat_id, msg.from.as_ref()).await?;
+        state
+            .bot
+            .send_message(chat_id, lang.text().not_permitted)
+            .await?;
+        return Ok(());
+    };
+    match cmd {
+        AdminCommand::Stats => {
+            let stats = state.db.stats().await?;
+            let text = format!(
+                "Linked critters: {}\nCritters who blocked the bot: {}\nPending messages: {}",
+                stats.linked, stats.blocked, stats.pending
+            );
+            state.bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::Resync(date) => {
+            let text = match date.trim() {
+                "" => {
+                    state.resync.all();
+                    "Resync of all days requested.".to_string()
+                }
+                date => match date.parse::<NaiveDate>() {
+                    Ok(date) => {
+                        state.resync.date(date);
+                        format!("Resync of {date} requested.")
+                    }
+                    Err(_) => "Usage: /resync [YYYY-MM-DD]".to_string(),
+                },
+            };
+            state.bot.send_message(chat_id, text).await?;
+        }
+        AdminCommand::Whois(uid) => {
+            let Ok(uid) = uid.trim().parse::<i64>() else {
+                state
+                    .bot
+                    .send_message(chat_id, "Usage: /whois <critter id>")
+                    .await?;
+                return Ok(());
+            };
+            let text = whois(&state, uid).await?;
+            state
+                .bot
+                .send_message(chat_id, text)
+                .parse_mode(render::PARSE_MODE)
+                .await?;
+        }
+        AdminCommand::Broadcast(input) => {
+            let (filter, text) = match broadcast::parse(&input) {
+                Ok((_, "")) => {
+                    state.bot.send_message(chat_id, BROADCAST_USAGE).await?;
+                    return Ok(());
+                }
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    state
+                        .bot
+                        .send_message(chat_id, format!("{err}\n\n{BROADCAST_USAGE}"))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let recipients = broadcast::recipients(&state, &filter).await?;
+            if recipients.is_empty() {
+                state
+                    .bot
+                    .send_message(chat_id, format!("There are no {filter}."))
+                    .parse_mode(render::PARSE_MODE)
+                    .await?;
+                return Ok(());
+            }
+            let id = state
+                .db
+                .create_broadcast(admin_uid, text, &recipients)
+                .await?;
+            let preview = render::event(
+                &Event::Broadcast {
+                    uid: admin_uid,
+                    id,
+                    text: text.into(),
+                },
+                Lang::En,
+            )?;
+            let text = format!(
+                "This goes out to {} {filter}:\n\n{preview}",
+                recipients.len()
+            );
+            let markup = InlineKeyboardMarkup::new([[
+                InlineKeyboardButton::callback(
+                    format!("Send to {}", recipients.len()),
+                    format!("{BROADCAST_PREFIX}{BROADCAST_SEND}:{id}"),
+                ),
+                InlineKeyboardButton::callback(
+                    "Cancel",
+                    format!("{BROADCAST_PREFIX}{BROADCAST_CANCEL}:{id}"),
+                ),
+            ]]);
+            state
+                .bot
+                .send_message(chat_id, text)
+                .parse_mode(render::PARSE_MODE)
+                .reply_markup(markup)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+const BROADCAST_USAGE: &str = "Usage: /broadcast <message>\n\nRecipients can be narrowed down by starting with any of these lines:\ntype=<angel type>\nlocation=<location>\nwithin=<minutes until the shift>\nstaff=<yes or no>";
+const BROADCAST_PREFIX: &str = "broadcast:";
+const BROADCAST_SEND: &str = "send";
+const BROADCAST_CANCEL: &str = "cancel";
+const BROADCAST_STATS: &str = "stats";
+
+/// Confirmation of a previewed broadcast and refreshing its delivery status, usable by any admin
+async fn broadcast_callback(
+    state: &State,
+    query: &CallbackQuery,
+    chat_id: ChatId,
+    msg: MessageId,
+    action: &str,
+) -> eyre::Result<()> {
+    if admin(state, Some(&query.from)).await?.is_none() {
+        return Ok(());
+    }
+    let Some((action, Ok(id))) = action
+        .split_once(':')
+        .map(|(action, id)| (action, id.parse::<i64>()))
+    else {
+        return Ok(());
+    };
+    let stats_button = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Refresh delivery status",
+        format!("{BROADCAST_PREFIX}{BROADCAST_STATS}:{id}"),
+    )]]);
+    match action {
+        BROADCAST_SEND => {
+            let since = Utc::now() - broadcast::PREVIEW_EXPIRY;
+            let Some(count) = state.db.send_broadcast(id, since).await? else {
+                // whatever is left unsent at this point is a preview which expired
+                if state.db.cancel_broadcast(id).await? {
+                    state
+                        .bot
+                        .edit_message_text(
+                            chat_id,
+                            msg,
+                            format!(
+                                "This preview is older than {} and its recipients may have changed, please send /broadcast again.",
+                                Span(broadcast::PREVIEW_EXPIRY, Lang::En)
+                            ),
+                        )
+                        .await?;
+                }
+                return Ok(());
+            };
+            info!(admin = query.from.id.0, id, count, "broadcast enqueued");
+            state
+                .bot
+                .edit_message_text(
+                    chat_id,
+                    msg,
+                    format!("Broadcast #{id} queued for {count} critters."),
+                )
+                .reply_markup(stats_button)
+                .await?;
+        }
+        BROADCAST_CANCEL if state.db.cancel_broadcast(id).await? => {
+            state
+                .bot
+                .edit_message_text(chat_id, msg, "Broadcast canceled.")
+                .await?;
+        }
+        BROADCAST_STATS => {
+            let text = broadcast::stats(state, id).await?;
+            state
+                .bot
+                .edit_message_text(chat_id, msg, text)
+                .reply_markup(stats_button)
+                .await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Summary of a critter for admins
+async fn whois(State { bot, db, .. }: &State, uid: i64) -> eyre::Result<String> {
+    let Some(critter) = db.critter(uid).await? else {
+        return Ok(format!("Critter {uid} has not linked their account."));
+    };
+    let mut text = format!("{}\n", bold(&format!("Critter {uid}")));
+    let username = bot
+        .get_chat(critter.chat)
+        .await
+        .ok()
+        .and_then(|chat| chat.username().map(str::to_string));
+    match username {
+        Some(username) => writeln!(text, "Telegram: @{} ({})", escape(&username), critter.chat)?,
+        None => writeln!(text, "Telegram: {}", critter.chat)?,
+    }
+    writeln!(
+        text,
+        "Language: {} (telegram: {})",
+        escape(critter.language.as_deref().unwrap_or("auto")),
+        escape(critter.tg_language.as_deref().unwrap_or("unknown"))
+    )?;
+    if let Some(at) = critter.blocked_at {
+        writeln!(text, "Blocked the bot {}", Relative(at, Lang::En))?;
+    }
+    let shifts = db
+        .critter_shifts(uid, Utc::now(), DateTime::<Utc>::MAX_UTC)
         .await?;
+    writeln!(text, "Upcoming shifts: {}", shifts.len())?;
+    if let Some(shift) = shifts.first() {
+        writeln!(
+            text,
+            "{}",
+            ShiftLine {
+                shift,
+                uid,
+                lang: Lang::En
+            }
+        )?;
+    }
+    Ok(text)
+}
 
+/// Critters who blocked the bot at some point get messaged again once they write to it.
+/// Also keeps track of the language of their telegram client.
+async fn seen(State { db, .. }: State, msg: Message) {
+    match db.set_blocked(msg.chat.id, false).await {
+        Ok(true) => debug!(chat = msg.chat.id.0, "critter unblocked the bot"),
+        Ok(false) => {}
+        Err(err) => error!("Failed to clear blocked flag: {err}"),
+    }
+    let Some(code) = msg.from.and_then(|u| u.language_code) else {
+        return;
+    };
+    if let Err(err) = db.set_tg_language(msg.chat.id, &code).await {
+        error!("Failed to store telegram language: {err}");
+    }
+}
+
+async fn default(State { bot, db, .. }: State, msg: Message) -> eyre::Result<()> {
+    let (Some(chat_id), Some(_)) = (msg.chat_id(), msg.text()) else {
+        return Ok(());
+    };
+    let lang = lang(&db, chat_id, msg.from.as_ref()).await?;
+    if db.check_if_present(chat_id).await?.is_some() {
+        bot.send_message(
+            chat_id,
+            format!("{}\n{}", lang.text().unknown_command, help(lang)),
+        )
+        .await?;
+    } else {
+        let t = lang.text();
+        bot.send_message(chat_id, format!("{}\n{}", t.unknown_command, t.login_hint))
+            .await?;
+    }
+    Ok(())
+}
+
+const MYSHIFTS_PREFIX: &str = "myshifts:";
+/// Callback data of buttons which are purely informational
+const NOOP: &str = "noop";
+
+/// Renders one page of upcoming shifts, every page covering a single day
+async fn my_shifts(
+    State { db, .. }: &State,
+    uid: i64,
+    page: usize,
+    lang: Lang,
+) -> eyre::Result<(String, Option<InlineKeyboardMarkup>)> {
+    let t = lang.text();
+    let now = Utc::now();
+    let mut days: Vec<(NaiveDate, Vec<Shift>)> = Vec::new();
+    for shift in db
+        .critter_shifts(uid, now, DateTime::<Utc>::MAX_UTC)
+        .await?
+    {
+        let day = shift.start.with_timezone(&shift.tz).date_naive();
+        match days.last_mut() {
+            Some((d, shifts)) if *d == day => shifts.push(shift),
+            _ => days.push((day, vec![shift])),
+        }
+    }
+    if days.is_empty() {
+        return Ok((t.no_shifts.into(), None));
+    }
+
+    let page = page.min(days.len() - 1);
+    let (day, shifts) = &days[page];
+    let mut text = fill(t.shifts_on, &[("date", &render::date(*day, lang))]);
+    text.push('\n');
+    for shift in shifts {
+        writeln!(text, "{}", ShiftLine { shift, uid, lang })?;
+    }
+
+    if days.len() == 1 {
+        return Ok((text, None));
+    }
+    let mut row = Vec::new();
+    if page > 0 {
+        row.push(InlineKeyboardButton::callback(
+            t.previous,
+            format!("{MYSHIFTS_PREFIX}{}", page - 1),
+        ));
+    }
+    row.push(InlineKeyboardButton::callback(
+        format!("{}/{}", page + 1, days.len()),
+        NOOP,
+    ));
+    if page + 1 < days.len() {
+        row.push(InlineKeyboardButton::callback(
+            t.next,
+            format!("{MYSHIFTS_PREFIX}{}", page + 1),
+        ));
+    }
+    Ok((text, Some(InlineKeyboardMarkup::new([row]))))
+}
+
+const SETTINGS_PREFIX: &str = "settings:";
+const CHECK_IN_PREFIX: &str = "checkin:";
+
+/// Buttons of a shift reminder letting the critter tell their managers whether they are coming
+pub fn check_in_buttons(shift: i64, picked: Option<CheckIn>, lang: Lang) -> InlineKeyboardMarkup {
+    let buttons = CheckIn::ALL
+        .into_iter()
+        .map(|status| {
+            let label = render::check_in(status, lang);
+            InlineKeyboardButton::callback(
+                match picked == Some(status) {
+                    true => format!("✅ {label}"),
+                    false => label.to_string(),
+                },
+                format!("{CHECK_IN_PREFIX}{shift}:{}", status.as_str()),
+            )
+        })
+        .collect::<Vec<_>>();
+    InlineKeyboardMarkup::new(buttons.chunks(2).map(<[_]>::to_vec))
+}
+
+async fn settings(
+    State { db, .. }: &State,
+    uid: i64,
+    lang: Lang,
+) -> eyre::Result<(String, InlineKeyboardMarkup)> {
+    let t = lang.text();
+    let span = |lead: i32| Span(TimeDelta::minutes(lead.into()), lang);
+    let mut leads = db.reminder_leads(uid).await?;
+    leads.sort_unstable_by(|a, b| b.cmp(a));
+
+    let text = if leads.is_empty() {
+        t.no_reminders.to_string()
+    } else {
+        let leads = leads
+            .iter()
+            .map(|lead| span(*lead).to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        fill(t.reminders, &[("leads", &leads)])
+    };
+    let buttons = LEAD_OPTIONS
+        .iter()
+        .map(|lead| {
+            InlineKeyboardButton::callback(
+                format!(
+                    "{} {}",
+                    if leads.contains(lead) { "✅" } else { "❌" },
+                    span(*lead)
+                ),
+                format!("{SETTINGS_PREFIX}{lead}"),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Ok((
+        format!("{text}\n{}", t.toggle_hint),
+        InlineKeyboardMarkup::new(buttons.chunks(3).map(<[_]>::to_vec)),
+    ))
+}
+
+const LANGUAGE_PREFIX: &str = "language:";
+/// Callback data of the button which drops the picked language again
+const LANGUAGE_AUTO: &str = "auto";
+
+fn languages(lang: Lang) -> InlineKeyboardMarkup {
+    let mut buttons = vec![InlineKeyboardButton::callback(
+        lang.text().language_auto,
+        format!("{LANGUAGE_PREFIX}{LANGUAGE_AUTO}"),
+    )];
+    buttons.extend(Lang::ALL.into_iter().map(|lang| {
+        InlineKeyboardButton::callback(
+            lang.text().name,
+            format!("{LANGUAGE_PREFIX}{}", lang.code()),
+        )
+    }));
+    InlineKeyboardMarkup::new([buttons])
+}
+
+async fn callback(state: State, query: CallbackQuery) -> eyre::Result<()> {
+    state.bot.answer_callback_query(query.id.clone()).await?;
+    let (Some(msg), Some(data)) = (&query.message, &query.data) else {
+        return Ok(());
+    };
+    let chat_id = msg.chat().id;
+    // admins may confirm broadcasts from within group chats as well
+    if let Some(action) = data.strip_prefix(BROADCAST_PREFIX) {
+        return broadcast_callback(&state, &query, chat_id, msg.id(), action).await;
+    }
+    let Some(uid) = state.db.check_if_present(chat_id).await? else {
+        return Ok(());
+    };
+    let lang = lang(&state.db, chat_id, Some(&query.from)).await?;
+
+    if let Some(page) = data.strip_prefix(MYSHIFTS_PREFIX) {
+        let Ok(page) = page.parse() else {
+            return Ok(());
+        };
+        let (text, markup) = my_shifts(&state, uid, page, lang).await?;
+        let req = state
+            .bot
+            .edit_message_text(chat_id, msg.id(), text)
+            .parse_mode(render::PARSE_MODE);
+        match markup {
+            Some(markup) => req.reply_markup(markup).await?,
+            None => req.await?,
+        };
+    } else if let Some(lead) = data.strip_prefix(SETTINGS_PREFIX) {
+        let Some(lead) = lead.parse().ok().filter(|l| LEAD_OPTIONS.contains(l)) else {
+            return Ok(());
+        };
+        let mut leads = state.db.reminder_leads(uid).await?;
+        if let Some(idx) = leads.iter().position(|l| *l == lead) {
+            leads.remove(idx);
+        } else {
+            leads.push(lead);
+        }
+        state.db.set_reminder_leads(uid, &leads).await?;
+
+        let (text, markup) = settings(&state, uid, lang).await?;
+        state
+            .bot
+            .edit_message_text(chat_id, msg.id(), text)
+            .reply_markup(markup)
+            .await?;
+    } else if let Some(code) = data.strip_prefix(LANGUAGE_PREFIX) {
+        let picked = match code {
+            LANGUAGE_AUTO => None,
+            code => match Lang::from_code(code) {
+                Some(lang) => Some(lang),
+                None => return Ok(()),
+            },
+        };
+        state.db.set_language(chat_id, picked).await?;
+
+        let lang = picked
+            .or_else(|| {
+                query
+                    .from
+                    .language_code
+                    .as_deref()
+                    .and_then(Lang::from_code)
+            })
+            .unwrap_or_default();
+        let t = lang.text();
+        let text = fill(t.language_set, &[("language", &t.name)]);
+        state.bot.edit_message_text(chat_id, msg.id(), text).await?;
+    } else if let Some(answer) = data.strip_prefix(CHECK_IN_PREFIX) {
+        let Some((shift, status)) = answer
+            .split_once(':')
+            .and_then(|(shift, status)| Some((shift.parse().ok()?, CheckIn::parse(status)?)))
+        else {
+            return Ok(());
+        };
+        // critters which got unassigned in the meantime may still tap the buttons
+        if !state.db.set_check_in(shift, uid, status).await? {
+            return Ok(());
+        }
+        let markup = check_in_buttons(shift, Some(status), lang);
+        if msg.regular_message().and_then(|m| m.reply_markup()) != Some(&markup) {
+            state
+                .bot
+                .edit_message_reply_markup(chat_id, msg.id())
+                .reply_markup(markup)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn spawn_command(state: State, msg: Message, cmd: Command) -> eyre::Result<()> {
+    tokio::spawn(async move {
+        let Err(err) = command(state, msg, cmd).await else {
+            return;
+        };
+        error!("Error in bot command occured: {err}");
+    });
+    Ok(())
+}
+
+async fn spawn_admin_command(state: State, msg: Message, cmd: AdminCommand) -> eyre::Result<()> {
+    tokio::spawn(async move {
+        let Err(err) = admin_command(state, msg, cmd).await else {
+            return;
+        };
+        error!("Error in bot admin command occured: {err}");
+    });
     Ok(())
 }
 
@@ -50,15 +801,73 @@ async fn spawn_default(state: State, msg: Message) -> eyre::Result<()> {
     Ok(())
 }
 
-pub async fn start_bot(state: State) {
-    let handler = Update::filter_message().endpoint(spawn_default);
+async fn spawn_callback(state: State, query: CallbackQuery) -> eyre::Result<()> {
+    tokio::spawn(async move {
+        let Err(err) = callback(state, query).await else {
+            return;
+        };
+        error!("Error in bot callback occured: {err}");
+    });
+    Ok(())
+}
 
-    Dispatcher::builder(state.bot.clone(), handler)
+/// Receives updates through a webhook if configured, long polling otherwise
+pub async fn start_bot(state: State, webhook: Option<webhooks::Options>) {
+    if let Err(err) = state.bot.set_my_commands(commands(Lang::default())).await {
+        error!("Failed to register bot commands: {err}");
+    }
+    for lang in Lang::ALL {
+        let res = state
+            .bot
+            .set_my_commands(commands(lang))
+            .language_code(lang.code())
+            .await;
+        if let Err(err) = res {
+            error!("Failed to register bot commands for {}: {err}", lang.code());
+        }
+    }
+
+    let handler = dptree::entry()
+        .branch(
+            Update::filter_message()
+                .inspect_async(seen)
+                .branch(
+                    dptree::entry()
+                        .filter_command::<Command>()
+                        .endpoint(spawn_command),
+                )
+                .branch(
+                    dptree::entry()
+                        .filter_command::<AdminCommand>()
+                        .endpoint(spawn_admin_command),
+                )
+                .endpoint(spawn_default),
+        )
+        .branch(Update::filter_callback_query().endpoint(spawn_callback));
+
+    let bot = state.bot.clone();
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
         .dependencies(dptree::deps![state])
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+
+    match webhook {
+        Some(options) => match webhooks::axum(bot, options).await {
+            Ok(listener) => {
+                dispatcher
+                    .dispatch_with_listener(
+                        listener,
+                        LoggingErrorHandler::with_custom_text("Error in telegram webhook"),
+                    )
+                    .await
+            }
+            Err(err) => {
+                error!("Failed to set up telegram webhook, falling back to polling: {err}");
+                dispatcher.dispatch().await
+            }
+        },
+        None => dispatcher.dispatch().await,
+    }
 
     std::process::exit(0);
 }
@@ -0,0 +1,203 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use color_eyre::eyre;
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Write},
+};
+
+use teloxide::utils::html::escape;
+
+use crate::{State, events::Shift};
+
+/// How long a preview may be confirmed for, filters on shifts go stale after a while
+pub const PREVIEW_EXPIRY: TimeDelta = TimeDelta::minutes(10);
+
+/// Narrows down the recipients of a broadcast to the critters with a matching shift
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Part of the angel type the critter is assigned as
+    pub r#type: Option<String>,
+    /// Part of the location of the shift
+    pub location: Option<String>,
+    /// Minutes from now within which the shift has to be running
+    pub within: Option<u32>,
+    pub staff: Option<bool>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.r#type.is_none()
+            && self.location.is_none()
+            && self.within.is_none()
+            && self.staff.is_none()
+    }
+
+    /// Critters of the shift matching the filter
+    fn critters<'a>(&'a self, shift: &'a Shift) -> impl Iterator<Item = i64> + 'a {
+        let location = self
+            .location
+            .as_ref()
+            .is_none_or(|location| contains(&shift.location, location));
+        shift
+            .critters
+            .iter()
+            .filter(move |(_, r#type, _, staff)| {
+                location
+                    && self.r#type.as_ref().is_none_or(|t| contains(r#type, t))
+                    && self.staff.is_none_or(|s| s == *staff)
+            })
+            .map(|c| c.2)
+    }
+}
+
+/// Description of the recipients as HTML
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "every linked critter");
+        }
+        write!(f, "critters")?;
+        if let Some(r#type) = &self.r#type {
+            write!(f, " assigned as {}", code(r#type))?;
+        }
+        match self.within {
+            Some(within) => write!(f, " with a shift within the next {within} min")?,
+            None => write!(f, " with a current or upcoming shift")?,
+        }
+        if let Some(location) = &self.location {
+            write!(f, " at {}", code(location))?;
+        }
+        match self.staff {
+            Some(true) => write!(f, " who are staff")?,
+            Some(false) => write!(f, " who are not staff")?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+fn code(value: &str) -> String {
+    format!("<code>{}</code>", escape(value))
+}
+
+fn contains(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+/// Splits the leading `key=value` filter lines off a broadcast, the rest is the message.
+/// Lines which look like a filter but are none are rejected instead of being sent out.
+pub fn parse(input: &str) -> Result<(Filter, &str), String> {
+    let mut filter = Filter::default();
+    let mut rest = input.trim();
+    loop {
+        let (line, tail) = rest.split_once('\n').unwrap_or((rest, ""));
+        let Some((key, value)) = line.split_once('=') else {
+            break;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            break;
+        }
+        match key {
+            "type" => filter.r#type = Some(value.into()),
+            "location" => filter.location = Some(value.into()),
+            "within" => {
+                let Ok(within) = value.parse() else {
+                    return Err(format!("\"{value}\" is not a number of minutes"));
+                };
+                filter.within = Some(within);
+            }
+            "staff" => {
+                filter.staff = Some(match value {
+                    "yes" | "true" => true,
+                    "no" | "false" => false,
+                    _ => return Err(format!("\"{value}\" is neither yes nor no")),
+                })
+            }
+            _ => return Err(format!("unknown filter \"{key}\"")),
+        }
+        rest = tail.trim_start();
+    }
+    Ok((filter, rest))
+}
+
+/// Linked critters matching the filter, shifts are taken from the stored ones
+pub async fn recipients(state: &State, filter: &Filter) -> eyre::Result<Vec<i64>> {
+    if filter.is_empty() {
+        return state.db.linked_critters(None).await;
+    }
+    let now = Utc::now();
+    let until = match filter.within {
+        Some(within) => now + TimeDelta::minutes(within.into()),
+        None => DateTime::<Utc>::MAX_UTC,
+    };
+    let shifts = state.db.shifts_between(now, until).await?;
+    let uids = shifts
+        .iter()
+        .flat_map(|shift| filter.critters(shift))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    state.db.linked_critters(Some(&uids)).await
+}
+
+/// Delivery status of a broadcast as reported by the outbox
+pub async fn stats(state: &State, id: i64) -> eyre::Result<String> {
+    let stats = state.db.broadcast_stats(id).await?;
+    let mut text = format!("Broadcast #{id}, as of {}:", Utc::now().format("%H:%M:%S"));
+    for (status, count) in stats {
+        write!(text, "\n{status}: {count}")?;
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_message() {
+        let (filter, text) = parse("Hall H closed, report to Hall C").unwrap();
+        assert!(filter.is_empty());
+        assert_eq!(text, "Hall H closed, report to Hall C");
+    }
+
+    #[test]
+    fn leading_filters() {
+        let (filter, text) = parse(
+            "type=Security\nlocation = Hall H\nwithin=90\nstaff=no\nHall H closed\nsee you=soon",
+        )
+        .unwrap();
+        assert_eq!(filter.r#type.as_deref(), Some("Security"));
+        assert_eq!(filter.location.as_deref(), Some("Hall H"));
+        assert_eq!(filter.within, Some(90));
+        assert_eq!(filter.staff, Some(false));
+        assert_eq!(text, "Hall H closed\nsee you=soon");
+    }
+
+    #[test]
+    fn message_with_equals_sign() {
+        let (filter, text) = parse("Note: 1+1=2").unwrap();
+        assert!(filter.is_empty());
+        assert_eq!(text, "Note: 1+1=2");
+    }
+
+    #[test]
+    fn rejects_bad_filters() {
+        assert!(parse("typ=Security\nHall H closed").is_err());
+        assert!(parse("within=soon\nHall H closed").is_err());
+        assert!(parse("staff=maybe\nHall H closed").is_err());
+    }
+
+    #[test]
+    fn describes_filter_as_html() {
+        let filter = Filter {
+            location: Some("Hall <H>".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.to_string(),
+            "critters with a current or upcoming shift at <code>Hall &lt;H&gt;</code>"
+        );
+    }
+}
@@ -1,17 +1,101 @@
-use chrono::NaiveDate;
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
 use color_eyre::eyre;
 use futures_util::StreamExt;
 use moka::future::Cache;
-use sqlx::{PgPool, query, types::Json};
-use std::{sync::Arc, time::Duration};
-use teloxide::types::ChatId;
-use tokio::sync::Semaphore;
+use sqlx::{PgPool, PgTransaction, query, types::Json};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use teloxide::types::{ChatId, MessageId};
+use tokio::sync::{Notify, Semaphore};
 use tracing::debug;
+use uuid::Uuid;
 
-use crate::events::Shift;
+use crate::{
+    events::{Event, Shift},
+    i18n::Lang,
+    metrics,
+};
 
-#[derive(serde::Deserialize)]
-pub struct UserId(u64);
+/// Minutes before a shift at which critters get reminded unless they changed it via `/settings`
+pub const DEFAULT_LEADS: [i32; 2] = [60, 15];
+/// Reminder lead times in minutes a critter can pick from, sorted ascending
+pub const LEAD_OPTIONS: [i32; 6] = [5, 10, 15, 30, 60, 120];
+
+pub struct OpenShiftPost {
+    pub chat: ChatId,
+    pub message: MessageId,
+    pub content: String,
+}
+
+pub struct OutboxEntry {
+    pub id: i64,
+    pub key: String,
+    pub event: Result<Event, serde_json::Error>,
+    pub attempts: i32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum OutboxStatus {
+    Sent,
+    Failed,
+    Blocked,
+    /// The recipient never linked their telegram account or blocked the bot
+    Skipped,
+}
+
+impl OutboxStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            OutboxStatus::Sent => "sent",
+            OutboxStatus::Failed => "failed",
+            OutboxStatus::Blocked => "blocked",
+            OutboxStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// Answer of a critter to the buttons of their shift reminder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckIn {
+    OnMyWay,
+    CheckedIn,
+    CantMakeIt,
+}
+
+impl CheckIn {
+    pub const ALL: [CheckIn; 3] = [CheckIn::OnMyWay, CheckIn::CheckedIn, CheckIn::CantMakeIt];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckIn::OnMyWay => "on_my_way",
+            CheckIn::CheckedIn => "checked_in",
+            CheckIn::CantMakeIt => "cant_make_it",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<CheckIn> {
+        CheckIn::ALL.into_iter().find(|c| c.as_str() == status)
+    }
+}
+
+/// Counters shown to admins via `/stats`
+pub struct Stats {
+    pub linked: i64,
+    pub blocked: i64,
+    pub pending: i64,
+}
+
+/// Everything we know about a linked critter
+pub struct Critter {
+    pub chat: ChatId,
+    pub language: Option<String>,
+    pub tg_language: Option<String>,
+    pub blocked_at: Option<DateTime<Utc>>,
+}
 
 // I'm aware that the implementations I made here are wonderfully inefficient, but I really don't care for now, this will be reimplemented eventually (right?!)
 #[derive(Clone)]
@@ -20,6 +104,7 @@ pub struct Database {
     c_cache: Cache<ChatId, Option<i64>>,
     u_cache: Cache<i64, Option<ChatId>>,
     lookup_limiter: Arc<Semaphore>,
+    outbox: Arc<Notify>,
 }
 
 impl Database {
@@ -33,13 +118,21 @@ impl Database {
                 .time_to_idle(Duration::from_secs(3600 * 6))
                 .build(),
             lookup_limiter: Arc::new(Semaphore::new(pq_limit)),
+            outbox: Arc::new(Notify::new()),
         }
     }
 
+    pub async fn ping(&self) -> eyre::Result<()> {
+        query!("select 1 as \"one!\"").fetch_one(&self.pool).await?;
+        Ok(())
+    }
+
     pub async fn check_if_present(&self, cid: ChatId) -> eyre::Result<Option<i64>> {
         if let Some(res) = self.c_cache.get(&cid).await {
+            metrics::cache_lookup("c_cache", true);
             return Ok(res);
         }
+        metrics::cache_lookup("c_cache", false);
         let res = query!("select id from critters where tgid = $1", cid.0)
             .fetch_optional(&self.pool)
             .await?
@@ -48,31 +141,285 @@ impl Database {
         Ok(res)
     }
 
-    pub async fn register(&self, uid: i64, cid: ChatId) -> eyre::Result<()> {
+    /// Links a critter to a chat, moving the critter over if it was linked to another chat before.
+    /// Returns the previously linked chat if there was one.
+    pub async fn register(
+        &self,
+        uid: i64,
+        cid: ChatId,
+        tg_language: Option<&str>,
+    ) -> eyre::Result<Option<ChatId>> {
+        let mut tx = self.pool.begin().await?;
+
+        let old = query!("select tgid from critters where id = $1 for update", uid)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|rec| ChatId(rec.tgid))
+            .filter(|old| *old != cid);
+        // the chat may only ever belong to a single critter
+        let stale = query!(
+            "delete from critters where tgid = $1 and id <> $2 returning id",
+            cid.0,
+            uid
+        )
+        .fetch_all(&mut *tx)
+        .await?;
         query!(
-            "insert into critters (id, tgid) values ($1, $2)",
+            "insert into critters (id, tgid, tg_language) values ($1, $2, $3) on conflict (id) do update set tgid = excluded.tgid, tg_language = excluded.tg_language, blocked_at = null",
             uid,
-            cid.0
+            cid.0,
+            tg_language
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
 
+        tx.commit().await?;
+
+        for rec in stale {
+            self.u_cache.invalidate(&rec.id).await;
+        }
+        if let Some(old) = old {
+            self.c_cache.invalidate(&old).await;
+        }
         self.c_cache.insert(cid, Some(uid)).await;
         self.u_cache.insert(uid, Some(cid)).await;
 
+        Ok(old)
+    }
+
+    /// Removes the link of a chat, returning the critter which was linked to it
+    pub async fn unlink(&self, cid: ChatId) -> eyre::Result<Option<i64>> {
+        let uid = query!("delete from critters where tgid = $1 returning id", cid.0)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|rec| rec.id);
+
+        self.c_cache.invalidate(&cid).await;
+        if let Some(uid) = uid {
+            self.u_cache.invalidate(&uid).await;
+        }
+
+        Ok(uid)
+    }
+
+    /// Flags the critter linked to a chat as having blocked the bot, or clears the flag again.
+    /// Returns whether anything changed.
+    pub async fn set_blocked(&self, cid: ChatId, blocked: bool) -> eyre::Result<bool> {
+        let uid = query!(
+            "update critters set blocked_at = case when $2 then now() at time zone 'utc' end where tgid = $1 and (blocked_at is null) = $2 returning id",
+            cid.0,
+            blocked
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|rec| rec.id);
+
+        let Some(uid) = uid else {
+            return Ok(false);
+        };
+        self.u_cache.invalidate(&uid).await;
+        Ok(true)
+    }
+
+    /// Language the critter picked via `/language`, if any
+    pub async fn language(&self, cid: ChatId) -> eyre::Result<Option<Lang>> {
+        Ok(
+            query!("select language from critters where tgid = $1", cid.0)
+                .fetch_optional(&self.pool)
+                .await?
+                .and_then(|rec| rec.language)
+                .and_then(|code| Lang::from_code(&code)),
+        )
+    }
+
+    /// Language notifications to a critter should be written in, falling back to the one of their telegram client
+    pub async fn critter_language(&self, uid: i64) -> eyre::Result<Option<Lang>> {
+        Ok(query!(
+            "select coalesce(language, tg_language) as language from critters where id = $1",
+            uid
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .and_then(|rec| rec.language)
+        .and_then(|code| Lang::from_code(&code)))
+    }
+
+    pub async fn set_language(&self, cid: ChatId, lang: Option<Lang>) -> eyre::Result<()> {
+        query!(
+            "update critters set language = $2 where tgid = $1",
+            cid.0,
+            lang.map(Lang::code)
+        )
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
 
+    /// Remembers the language reported by the telegram client of a chat
+    pub async fn set_tg_language(&self, cid: ChatId, code: &str) -> eyre::Result<()> {
+        query!(
+            "update critters set tg_language = $2 where tgid = $1 and tg_language is distinct from $2",
+            cid.0,
+            code
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Token of the calendar feed of a critter, generated on first use
+    pub async fn calendar_token(&self, uid: i64) -> eyre::Result<String> {
+        Ok(query!(
+            "update critters set calendar_token = coalesce(calendar_token, $2) where id = $1 returning calendar_token as \"calendar_token!\"",
+            uid,
+            Uuid::new_v4().simple().to_string()
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .calendar_token)
+    }
+
+    pub async fn calendar_owner(&self, token: &str) -> eyre::Result<Option<i64>> {
+        Ok(
+            query!("select id from critters where calendar_token = $1", token)
+                .fetch_optional(&self.pool)
+                .await?
+                .map(|rec| rec.id),
+        )
+    }
+
+    pub async fn stats(&self) -> eyre::Result<Stats> {
+        let rec = query!(
+            "select count(*) filter (where blocked_at is null) as \"linked!\", count(*) filter (where blocked_at is not null) as \"blocked!\", (select count(*) from outbox where status = 'pending') as \"pending!\" from critters"
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Stats {
+            linked: rec.linked,
+            blocked: rec.blocked,
+            pending: rec.pending,
+        })
+    }
+
+    pub async fn critter(&self, uid: i64) -> eyre::Result<Option<Critter>> {
+        Ok(query!(
+            "select tgid, language, tg_language, blocked_at from critters where id = $1",
+            uid
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|rec| Critter {
+            chat: ChatId(rec.tgid),
+            language: rec.language,
+            tg_language: rec.tg_language,
+            blocked_at: rec.blocked_at.map(|at| at.and_utc()),
+        }))
+    }
+
+    /// Critters which did not block the bot, optionally narrowed down to the given ones
+    pub async fn linked_critters(&self, only: Option<&[i64]>) -> eyre::Result<Vec<i64>> {
+        Ok(query!(
+            "select id from critters where blocked_at is null and ($1::bigint[] is null or id = any($1)) order by id",
+            only as Option<&[i64]>
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|rec| rec.id)
+        .collect())
+    }
+
+    pub async fn create_broadcast(
+        &self,
+        admin: i64,
+        text: &str,
+        recipients: &[i64],
+    ) -> eyre::Result<i64> {
+        Ok(query!(
+            "insert into broadcasts (admin, text, recipients) values ($1, $2, $3) returning id",
+            admin,
+            text,
+            recipients
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .id)
+    }
+
+    /// Enqueues a broadcast for all of its recipients which did not block the bot since, returning how many there are.
+    /// `None` if it does not exist, was sent already or was created before `since`.
+    pub async fn send_broadcast(
+        &self,
+        id: i64,
+        since: DateTime<Utc>,
+    ) -> eyre::Result<Option<usize>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(rec) = query!(
+            "update broadcasts b set sent_at = now() at time zone 'utc' where id = $1 and sent_at is null and created >= $2 returning text, array(select id from critters where blocked_at is null and id = any(b.recipients) order by id) as \"recipients!\"",
+            id,
+            since.naive_utc()
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let text = Arc::<str>::from(rec.text);
+        let events = rec
+            .recipients
+            .into_iter()
+            .map(|uid| Event::Broadcast {
+                uid,
+                id,
+                text: text.clone(),
+            })
+            .collect::<Vec<_>>();
+        self.enqueue(&mut tx, &events).await?;
+        tx.commit().await?;
+        Ok(Some(events.len()))
+    }
+
+    /// Drops a broadcast which was not sent yet, returning whether there was one
+    pub async fn cancel_broadcast(&self, id: i64) -> eyre::Result<bool> {
+        Ok(query!(
+            "delete from broadcasts where id = $1 and sent_at is null",
+            id
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Amount of messages of a broadcast per outbox status
+    pub async fn broadcast_stats(&self, id: i64) -> eyre::Result<Vec<(String, i64)>> {
+        Ok(query!(
+            "select status, count(*) as \"count!\" from outbox where key like $1 group by status order by status",
+            format!("broadcast:{id}:%")
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|rec| (rec.status, rec.count))
+        .collect())
+    }
+
+    /// Looks up the chat of a critter, critters which blocked the bot are treated as unlinked
     pub async fn get_chat_id(&self, uid: i64) -> eyre::Result<Option<ChatId>> {
         if let Some(res) = self.u_cache.get(&uid).await {
+            metrics::cache_lookup("u_cache", true);
             return Ok(res);
         }
+        metrics::cache_lookup("u_cache", false);
         let _ = self.lookup_limiter.acquire().await;
 
-        let res = query!("select tgid from critters where id = $1", uid as i64)
-            .fetch_optional(&self.pool)
-            .await?
-            .map(|rec| ChatId(rec.tgid));
+        let res = query!(
+            "select tgid from critters where id = $1 and blocked_at is null",
+            uid
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|rec| ChatId(rec.tgid));
 
         self.u_cache.insert(uid, res).await;
 
@@ -81,55 +428,327 @@ impl Database {
 
     pub async fn insert_shift(&self, shift: &Shift) -> eyre::Result<()> {
         debug!("{}", shift.id);
+        let mut tx = self.pool.begin().await?;
         query!(
             "insert into shifts (id, start, stop, meta) values ($1, $2, $3, $4)",
             shift.id,
             shift.start.naive_utc(),
             shift.end.naive_utc(),
-            serde_json::to_value(&shift)?,
+            serde_json::to_value(shift)?,
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
+        Self::assign(&mut tx, shift).await?;
+        tx.commit().await?;
 
         Ok(())
     }
 
-    pub async fn update_shift(&self, shift: &Shift) -> eyre::Result<()> {
+    pub async fn update_shift(&self, shift: &Shift, events: &[Event]) -> eyre::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let moved = query!(
+            "select start <> $2 as \"moved!\" from shifts where id = $1 for update",
+            shift.id,
+            shift.start.naive_utc(),
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some_and(|rec| rec.moved);
+        if moved {
+            // reminders and rosters were sent for the old time, everybody gets them again for the new one
+            query!("delete from reminders where shift = $1", shift.id)
+                .execute(&mut *tx)
+                .await?;
+            // as do the check-in answers to those reminders
+            query!("delete from check_ins where shift = $1", shift.id)
+                .execute(&mut *tx)
+                .await?;
+            query!(
+                "update manager_assignments set informed = false where shift = $1",
+                shift.id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
         query!(
-            "update shifts set meta = $1, start = $2, stop = $3 where id = $4",
-            serde_json::to_value(&shift)?,
+            "update shifts set meta = $1, start = $2, stop = $3, sequence = sequence + (start <> $2 or stop <> $3)::int where id = $4",
+            serde_json::to_value(shift)?,
             shift.start.naive_utc(),
             shift.end.naive_utc(),
             shift.id,
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
+        .await?;
+        query!("delete from assignments where shift = $1", shift.id)
+            .execute(&mut *tx)
+            .await?;
+        Self::assign(&mut tx, shift).await?;
+        self.enqueue(&mut tx, events).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn assign(tx: &mut PgTransaction<'_>, shift: &Shift) -> eyre::Result<()> {
+        query!(
+            "insert into assignments (shift, critter) select $1, unnest($2::bigint[]) on conflict do nothing",
+            shift.id,
+            &shift.critters.iter().map(|c| c.2).collect::<Vec<_>>(),
+        )
+        .execute(&mut **tx)
         .await?;
 
+        // keep the informed state of managers which are still assigned
+        let managers = shift.managers.iter().map(|m| m.1).collect::<Vec<_>>();
+        query!(
+            "delete from manager_assignments where shift = $1 and not (critter = any($2))",
+            shift.id,
+            &managers,
+        )
+        .execute(&mut **tx)
+        .await?;
+        query!(
+            "insert into manager_assignments (shift, critter) select $1, unnest($2::bigint[]) on conflict do nothing",
+            shift.id,
+            &managers,
+        )
+        .execute(&mut **tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Marks all managers of a shift as informed, enqueueing the event built for every manager which was not informed yet
+    pub async fn inform_managers(
+        &self,
+        shift: i64,
+        event: impl Fn(i64) -> Event,
+    ) -> eyre::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let events = query!(
+            "update manager_assignments set informed = true where shift = $1 and not informed returning critter",
+            shift
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|rec| event(rec.critter))
+        .collect::<Vec<_>>();
+        self.enqueue(&mut tx, &events).await?;
+        tx.commit().await?;
         Ok(())
     }
 
-    pub async fn delete_shift(&self, id: i64) -> eyre::Result<()> {
+    /// Stores the answer of a critter to their shift reminder, returning whether they are assigned to the shift
+    pub async fn set_check_in(&self, shift: i64, uid: i64, status: CheckIn) -> eyre::Result<bool> {
+        Ok(query!(
+            "insert into check_ins (shift, critter, status) select shift, critter, $3 from assignments where shift = $1 and critter = $2 on conflict (shift, critter) do update set status = excluded.status, at = excluded.at",
+            shift,
+            uid,
+            status.as_str()
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Latest answer of every critter of a shift along with when they gave it
+    pub async fn check_ins(
+        &self,
+        shift: i64,
+    ) -> eyre::Result<HashMap<i64, (CheckIn, DateTime<Utc>)>> {
+        Ok(query!(
+            "select critter, status, at from check_ins where shift = $1",
+            shift
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .filter_map(|rec| {
+            Some((
+                rec.critter,
+                (CheckIn::parse(&rec.status)?, rec.at.and_utc()),
+            ))
+        })
+        .collect())
+    }
+
+    pub async fn shift(&self, id: i64) -> eyre::Result<Option<Shift>> {
+        Ok(query!(
+            "select meta as \"meta: Json<Shift>\" from shifts where id = $1",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|rec| rec.meta.0))
+    }
+
+    /// Revision of the calendar entries of a shift
+    pub async fn shift_sequence(&self, id: i64) -> eyre::Result<Option<i32>> {
+        Ok(query!("select sequence from shifts where id = $1", id)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|rec| rec.sequence))
+    }
+
+    pub async fn shift_sequences(&self, ids: &[i64]) -> eyre::Result<HashMap<i64, i32>> {
+        Ok(
+            query!("select id, sequence from shifts where id = any($1)", ids)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|rec| (rec.id, rec.sequence))
+                .collect(),
+        )
+    }
+
+    pub async fn delete_shift(&self, id: i64, events: &[Event]) -> eyre::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        query!("delete from assignments where shift = $1", id)
+            .execute(&mut *tx)
+            .await?;
+        query!("delete from manager_assignments where shift = $1", id)
+            .execute(&mut *tx)
+            .await?;
         query!("delete from shifts where id = $1", id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        self.enqueue(&mut tx, events).await?;
+        tx.commit().await?;
 
         Ok(())
     }
 
-    pub async fn has_been_notified(&self, id: i64) -> eyre::Result<bool> {
-        Ok(query!("select notified from shifts where id = $1", id)
-            .fetch_one(&self.pool)
+    pub async fn understaffed_shifts(&self) -> eyre::Result<HashSet<i64>> {
+        Ok(query!("select id from shifts where understaffed")
+            .fetch_all(&self.pool)
             .await?
-            .notified)
+            .into_iter()
+            .map(|rec| rec.id)
+            .collect())
     }
 
-    pub async fn notify(&self, id: i64, val: bool) -> eyre::Result<()> {
-        query!("update shifts set notified = $1 where id = $2", val, id)
+    pub async fn set_understaffed(&self, id: i64, val: bool, events: &[Event]) -> eyre::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        query!("update shifts set understaffed = $1 where id = $2", val, id)
+            .execute(&mut *tx)
+            .await?;
+        self.enqueue(&mut tx, events).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn open_shift_posts(&self) -> eyre::Result<HashMap<i64, OpenShiftPost>> {
+        Ok(
+            query!("select shift, chat, message, content from open_shift_posts")
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|rec| {
+                    (
+                        rec.shift,
+                        OpenShiftPost {
+                            chat: ChatId(rec.chat),
+                            message: MessageId(rec.message),
+                            content: rec.content,
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub async fn set_open_shift_post(&self, shift: i64, post: &OpenShiftPost) -> eyre::Result<()> {
+        query!(
+            "insert into open_shift_posts (shift, chat, message, content) values ($1, $2, $3, $4) on conflict (shift) do update set chat = excluded.chat, message = excluded.message, content = excluded.content",
+            shift,
+            post.chat.0,
+            post.message.0,
+            post.content,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_open_shift_post(&self, shift: i64) -> eyre::Result<()> {
+        query!("delete from open_shift_posts where shift = $1", shift)
             .execute(&self.pool)
             .await?;
         Ok(())
     }
 
+    pub async fn reminder_leads(&self, uid: i64) -> eyre::Result<Vec<i32>> {
+        Ok(
+            query!("select leads from reminder_leads where critter = $1", uid)
+                .fetch_optional(&self.pool)
+                .await?
+                .map_or_else(|| DEFAULT_LEADS.to_vec(), |rec| rec.leads),
+        )
+    }
+
+    pub async fn set_reminder_leads(&self, uid: i64, leads: &[i32]) -> eyre::Result<()> {
+        query!(
+            "insert into reminder_leads (critter, leads) values ($1, $2) on conflict (critter) do update set leads = excluded.leads",
+            uid,
+            leads
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Reminder lead times of all critters which changed them, everybody else uses [`DEFAULT_LEADS`]
+    pub async fn all_reminder_leads(&self) -> eyre::Result<HashMap<i64, Vec<i32>>> {
+        Ok(query!("select critter, leads from reminder_leads")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|rec| (rec.critter, rec.leads))
+            .collect())
+    }
+
+    /// All reminders already sent out for the given shifts as (shift, critter, lead)
+    pub async fn sent_reminders(&self, shifts: &[i64]) -> eyre::Result<HashSet<(i64, i64, i32)>> {
+        Ok(query!(
+            "select shift, critter, lead from reminders where shift = any($1)",
+            shifts
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|rec| (rec.shift, rec.critter, rec.lead))
+        .collect())
+    }
+
+    pub async fn remind(
+        &self,
+        shift: i64,
+        uid: i64,
+        leads: &[i32],
+        event: Event,
+    ) -> eyre::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        query!(
+            "insert into reminders (shift, critter, lead) select $1, $2, unnest($3::integer[]) on conflict do nothing",
+            shift,
+            uid,
+            leads
+        )
+        .execute(&mut *tx)
+        .await?;
+        self.enqueue(&mut tx, &[event]).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn shift_start(&self, id: i64) -> eyre::Result<Option<DateTime<Utc>>> {
+        Ok(query!("select start from shifts where id = $1", id)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|rec| rec.start.and_utc()))
+    }
+
     pub async fn has_day_been_notified(&self, date: NaiveDate) -> eyre::Result<Option<bool>> {
         Ok(
             query!("select notified from dates where \"date\" = $1", date)
@@ -139,16 +758,112 @@ impl Database {
         )
     }
 
-    pub async fn notify_day(&self, date: NaiveDate, val: bool) -> eyre::Result<()> {
+    pub async fn notify_day(
+        &self,
+        date: NaiveDate,
+        val: bool,
+        events: &[Event],
+    ) -> eyre::Result<()> {
+        let mut tx = self.pool.begin().await?;
         query!(
             "update dates set notified = $1 where \"date\" = $2",
             val,
             date
         )
+        .execute(&mut *tx)
+        .await?;
+        self.enqueue(&mut tx, events).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Persists events for the outbox worker, events which have already been enqueued before are dropped
+    async fn enqueue(&self, tx: &mut PgTransaction<'_>, events: &[Event]) -> eyre::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let at = Utc::now();
+        for event in events {
+            query!(
+                "insert into outbox (key, target, event) values ($1, $2, $3) on conflict (key) do nothing",
+                event.key(at),
+                event.target().to_string(),
+                serde_json::to_value(event)?,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        // the worker only gets to see the events once the transaction is through
+        self.outbox.notify_one();
+        Ok(())
+    }
+
+    /// Leases a batch of due events to the caller, they get handed out again once the lease runs out.
+    /// Only the oldest pending event of every recipient is handed out, so they arrive in order.
+    pub async fn claim_outbox(
+        &self,
+        limit: i64,
+        lease: TimeDelta,
+    ) -> eyre::Result<Vec<OutboxEntry>> {
+        let now = Utc::now();
+        let mut entries = query!(
+            "update outbox set attempts = attempts + 1, next_attempt = $2 where id in (select id from outbox o where status = 'pending' and next_attempt <= $1 and not exists (select 1 from outbox p where p.target = o.target and p.status = 'pending' and p.id < o.id) order by id limit $3 for update skip locked) returning id, key, event, attempts",
+            now.naive_utc(),
+            (now + lease).naive_utc(),
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|rec| OutboxEntry {
+            id: rec.id,
+            key: rec.key,
+            event: serde_json::from_value(rec.event),
+            attempts: rec.attempts,
+        })
+        .collect::<Vec<_>>();
+        entries.sort_by_key(|e| e.id);
+        Ok(entries)
+    }
+
+    pub async fn finish_outbox(
+        &self,
+        id: i64,
+        status: OutboxStatus,
+        error: Option<String>,
+    ) -> eyre::Result<()> {
+        query!(
+            "update outbox set status = $1, error = $2 where id = $3",
+            status.as_str(),
+            error,
+            id
+        )
         .execute(&self.pool)
         .await?;
         Ok(())
     }
+
+    pub async fn retry_outbox(
+        &self,
+        id: i64,
+        at: DateTime<Utc>,
+        error: String,
+    ) -> eyre::Result<()> {
+        query!(
+            "update outbox set next_attempt = $1, error = $2 where id = $3",
+            at.naive_utc(),
+            error,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Resolves once events have been enqueued since the last call
+    pub async fn outbox_notified(&self) {
+        self.outbox.notified().await
+    }
     //     let mut tx = self.pool.begin().await?;
 
     //     let Some(meta): Option<Shift> = query!(
@@ -179,10 +894,20 @@ impl Database {
 
     //     todo!()
 
-    pub async fn posts(&self, date: NaiveDate) -> eyre::Result<Vec<Shift>> {
+    /// All shifts starting on the given local date
+    pub async fn posts(&self, date: NaiveDate, tz: Tz) -> eyre::Result<Vec<Shift>> {
+        let midnight = |date: NaiveDate| {
+            let naive = date.and_time(NaiveTime::MIN);
+            naive
+                .and_local_timezone(tz)
+                .earliest()
+                .map_or_else(|| naive.and_utc(), |d| d.to_utc())
+                .naive_utc()
+        };
         let mut stream = query!(
-            "select meta as \"meta: Json<Shift>\" from shifts where date(start) = $1",
-            date
+            "select meta as \"meta: Json<Shift>\" from shifts where start >= $1 and start < $2",
+            midnight(date),
+            midnight(date + Days::new(1)),
         )
         .fetch(&self.pool);
         let mut shifts = Vec::new();
@@ -193,6 +918,46 @@ impl Database {
         Ok(shifts)
     }
 
+    /// All shifts overlapping the given window
+    pub async fn shifts_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> eyre::Result<Vec<Shift>> {
+        let mut stream = query!(
+            "select meta as \"meta: Json<Shift>\" from shifts where stop > $1 and start < $2",
+            from.naive_utc(),
+            to.naive_utc(),
+        )
+        .fetch(&self.pool);
+        let mut shifts = Vec::new();
+        while let Some(shift) = stream.next().await {
+            shifts.push(shift?.meta.0);
+        }
+        Ok(shifts)
+    }
+
+    /// All shifts of a critter overlapping the given window, ordered by start time
+    pub async fn critter_shifts(
+        &self,
+        uid: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> eyre::Result<Vec<Shift>> {
+        let mut stream = query!(
+            "select meta as \"meta: Json<Shift>\" from shifts join assignments on shift = id where critter = $1 and stop > $2 and start < $3 order by start",
+            uid,
+            from.naive_utc(),
+            to.naive_utc(),
+        )
+        .fetch(&self.pool);
+        let mut shifts = Vec::new();
+        while let Some(shift) = stream.next().await {
+            shifts.push(shift?.meta.0);
+        }
+        Ok(shifts)
+    }
+
     pub async fn sync_dates(&self, cur_dates: &[NaiveDate]) -> eyre::Result<()> {
         let dates = query!("select \"date\" from dates")
             .fetch_all(&self.pool)
@@ -217,3 +982,91 @@ impl Database {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::shift;
+
+    #[sqlx::test]
+    async fn every_reminder_lead_gets_its_own_message(pool: PgPool) -> eyre::Result<()> {
+        let db = Database::new(pool.clone(), 1);
+        let shift = shift(1);
+        db.insert_shift(&shift).await?;
+        for lead in [60, 15] {
+            let event = Event::UserUpcoming {
+                uid: 7,
+                shift: shift.clone(),
+                lead,
+                sequence: 0,
+            };
+            db.remind(shift.id, 7, &[lead], event).await?;
+        }
+
+        let count = query!("select count(*) as \"count!\" from outbox")
+            .fetch_one(&pool)
+            .await?
+            .count;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn broadcasts_skip_blocked_and_expired(pool: PgPool) -> eyre::Result<()> {
+        let db = Database::new(pool.clone(), 1);
+        db.register(1, ChatId(101), None).await?;
+        db.register(2, ChatId(102), None).await?;
+        let id = db.create_broadcast(1, "hello", &[1, 2]).await?;
+        db.set_blocked(ChatId(102), true).await?;
+
+        let expired = db.create_broadcast(1, "hello", &[1, 2]).await?;
+        let later = Utc::now() + TimeDelta::minutes(1);
+        assert_eq!(db.send_broadcast(expired, later).await?, None);
+
+        let since = Utc::now() - TimeDelta::minutes(1);
+        assert_eq!(db.send_broadcast(id, since).await?, Some(1));
+        assert_eq!(db.send_broadcast(id, since).await?, None);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn moved_shifts_are_reminded_again(pool: PgPool) -> eyre::Result<()> {
+        let db = Database::new(pool.clone(), 1);
+        let mut shift = shift(1);
+        db.insert_shift(&shift).await?;
+        let event = Event::UserUpcoming {
+            uid: 7,
+            shift: shift.clone(),
+            lead: 60,
+            sequence: 0,
+        };
+        db.remind(shift.id, 7, &[60], event).await?;
+        db.set_check_in(shift.id, 7, CheckIn::OnMyWay).await?;
+        db.inform_managers(shift.id, |uid| Event::ManagerUpcoming {
+            uid,
+            shift: shift.clone(),
+        })
+        .await?;
+
+        // other changes keep what was sent
+        shift.req = 3;
+        db.update_shift(&shift, &[]).await?;
+        assert_eq!(db.sent_reminders(&[shift.id]).await?.len(), 1);
+        assert_eq!(db.check_ins(shift.id).await?.len(), 1);
+
+        shift.start += TimeDelta::hours(1);
+        shift.end += TimeDelta::hours(1);
+        db.update_shift(&shift, &[]).await?;
+        assert!(db.sent_reminders(&[shift.id]).await?.is_empty());
+        assert!(db.check_ins(shift.id).await?.is_empty());
+        let informed = query!(
+            "select informed from manager_assignments where shift = $1",
+            shift.id
+        )
+        .fetch_one(&pool)
+        .await?
+        .informed;
+        assert!(!informed);
+        Ok(())
+    }
+}
@@ -1,17 +1,30 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Days, NaiveDate, TimeDelta, Utc};
 use chrono_tz::Tz;
 use color_eyre::eyre::{self, WrapErr};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     fmt::Display,
-    sync::Arc,
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
-use teloxide::{prelude::Requester, types::ChatId};
-use tokio::{sync::mpsc::UnboundedReceiver, time::sleep};
+use teloxide::{
+    payloads::{SendDocumentSetters, SendMessageSetters},
+    prelude::{Request, Requester},
+    types::{ChatId, InlineKeyboardMarkup, InputFile},
+};
+use tokio::{sync::Notify, time::sleep};
 use tracing::{debug, error, trace};
 
-use crate::State;
+use crate::{
+    State, board, bot,
+    db::{DEFAULT_LEADS, LEAD_OPTIONS},
+    i18n::Lang,
+    ics::{Calendar, Entry},
+    metrics, render,
+};
+
+/// Longest caption telegram accepts on a document
+const CAPTION_LIMIT: usize = 1024;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Shift {
@@ -28,22 +41,50 @@ pub struct Shift {
     pub ppe: bool,
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum Event {
     UserUpcoming {
         uid: i64,
         shift: Shift,
+        /// Smallest lead in minutes the reminder was sent for
+        #[serde(default)]
+        lead: i32,
+        /// Calendar revision of the shift when the event was detected
+        #[serde(default)]
+        sequence: i32,
     },
     UserTimeChanged {
         uid: i64,
         shift: Shift,
-        old_start: DateTime<Tz>,
-        old_end: DateTime<Tz>,
+        old_start: DateTime<Utc>,
+        old_end: DateTime<Utc>,
+        /// Calendar revision of the shift when the event was detected
+        #[serde(default)]
+        sequence: i32,
     },
     UserCanceled {
         uid: i64,
         shift: Shift,
+        /// Calendar revision of the cancellation, the shift is gone by the time it is sent
+        #[serde(default)]
+        sequence: i32,
+    },
+    UserAssigned {
+        uid: i64,
+        shift: Shift,
+        /// Calendar revision of the shift when the event was detected
+        #[serde(default)]
+        sequence: i32,
+    },
+    UserUnassigned {
+        uid: i64,
+        role: Arc<str>,
+        shift: Shift,
+        /// Calendar revision of the shift when the event was detected
+        #[serde(default)]
+        sequence: i32,
     },
-    /// TODO: wait for rusty
     ManagerUpcoming {
         uid: i64,
         shift: Shift,
@@ -52,43 +93,194 @@ pub enum Event {
         uid: i64,
         next: Vec<Shift>,
     },
+    Understaffed {
+        to: Recipient,
+        shift: Shift,
+    },
+    Staffed {
+        to: Recipient,
+        shift: Shift,
+    },
+    /// Message an admin sent out via `/broadcast`
+    Broadcast {
+        uid: i64,
+        #[serde(default)]
+        id: i64,
+        text: Arc<str>,
+    },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+pub enum Recipient {
+    Critter(i64),
+    Chat(ChatId),
+}
+
+impl Display for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recipient::Critter(uid) => write!(f, "critter-{uid}"),
+            Recipient::Chat(cid) => write!(f, "chat-{cid}"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum ShiftDiff {
     Created,
-    Updated,
+    Updated(CritterDiff),
     TimeUpdated {
         old_start: DateTime<Utc>,
         old_end: DateTime<Utc>,
+        critters: CritterDiff,
     },
     Deleted,
 }
 
+impl ShiftDiff {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ShiftDiff::Created => "created",
+            ShiftDiff::Updated(_) => "updated",
+            ShiftDiff::TimeUpdated { .. } => "time_updated",
+            ShiftDiff::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CritterDiff {
+    pub assigned: Vec<i64>,
+    /// Role and id of every critter which is no longer part of the shift
+    pub unassigned: Vec<(Arc<str>, i64)>,
+}
+
+/// Out of band sync requests, e.g. from the crittersystem calling our webhook
+#[derive(Clone, Default)]
+pub struct Resync {
+    pending: Arc<Mutex<PendingResync>>,
+    notify: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct PendingResync {
+    dates: BTreeSet<NaiveDate>,
+    all: bool,
+}
+
+impl Resync {
+    pub fn date(&self, date: NaiveDate) {
+        self.pending.lock().unwrap().dates.insert(date);
+        self.notify.notify_one();
+    }
+
+    /// Resyncs every day within the sync window
+    pub fn all(&self) {
+        self.pending.lock().unwrap().all = true;
+        self.notify.notify_one();
+    }
+
+    fn take(&self) -> PendingResync {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
 #[tracing::instrument(name = "event_poll", skip(state))]
 pub async fn start_event_processor(state: State) -> eyre::Result<()> {
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-    tokio::spawn(distribute(state.clone(), rx));
-
+    let mut last_future: Option<Instant> = None;
     loop {
         trace!("polling data...");
+        let timer = metrics::POLL_DURATION.start_timer();
 
         trace!("syncing dates...");
-        let dates = state.api.dates().await.wrap_err("api dates")?;
-        state.db.sync_dates(&dates).await.wrap_err("db date sync")?;
-
-        let date = Utc::now().with_timezone(&state.tz).date_naive();
-        trace!(date = date.to_string(), "syncing posts of the day...");
-        let old = state.db.posts(date).await.wrap_err("db posts pull")?;
-        let new = state
+        let dates = state
             .api
-            .shifts(date, state.tz)
+            .dates()
             .await
-            .wrap_err("api posts")?;
+            .inspect_err(|_| metrics::API_ERRORS.with_label_values(&["dates"]).inc())
+            .wrap_err("api dates")?;
+        state.db.sync_dates(&dates).await.wrap_err("db date sync")?;
 
         let now = Utc::now();
+        let date = now.with_timezone(&state.tz).date_naive();
+        // the next day is polled just as often once reminders, rosters, alerts or posts for it may become due
+        let mut sync = vec![date];
+        let ahead = [
+            LEAD_OPTIONS[LEAD_OPTIONS.len() - 1] as u32,
+            state.manager_lead,
+            state.understaffed_window,
+            state.open_shifts_window,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or_default();
+        let horizon = (now + TimeDelta::minutes(i64::from(ahead)))
+            .with_timezone(&state.tz)
+            .date_naive();
+        if horizon != date {
+            sync.push(horizon);
+        }
+        let until = date + Days::new(state.sync_days as u64);
+        let resync = state.resync.take();
+        sync.extend(resync.dates);
+        if resync.all
+            || last_future
+                .is_none_or(|last| last.elapsed().as_secs() >= state.future_poll_interval as u64)
+        {
+            sync.extend(dates.iter().filter(|d| **d > horizon && **d <= until));
+            last_future = Some(Instant::now());
+        }
+        sync.sort_unstable();
+        sync.dedup();
+
+        let mut old = Vec::new();
+        let mut new = Vec::new();
+        pull(&state, &sync, &mut old, &mut new).await?;
+        // a shift might have moved to a day which was not pulled, it is only gone once it is missing from all of them
+        if vanished(&old, &new).next().is_some() {
+            let rest = dates
+                .iter()
+                .copied()
+                .filter(|d| *d >= date && *d <= until && !sync.contains(d))
+                .collect::<Vec<_>>();
+            pull(&state, &rest, &mut old, &mut new).await?;
+        }
+        // shifts moving in from a day which was not pulled are already stored
+        for id in appeared(&old, &new) {
+            if let Some(shift) = state.db.shift(id).await.wrap_err("db shift pull")? {
+                old.push(shift);
+            }
+        }
+
+        let leads = state
+            .db
+            .all_reminder_leads()
+            .await
+            .wrap_err("db reminder leads")?;
+        let mut sent = state
+            .db
+            .sent_reminders(&new.iter().map(|s| s.id).collect::<Vec<_>>())
+            .await
+            .wrap_err("db sent reminders")?;
+        let understaffed = state
+            .db
+            .understaffed_shifts()
+            .await
+            .wrap_err("db understaffed shifts")?;
+
+        let mut deleted = Vec::new();
         for (shift, change) in scan_iter(&old, &new) {
             debug!("{change:?} - {}", shift.id);
+            if let Some(change) = &change {
+                metrics::SHIFT_DIFFS
+                    .with_label_values(&[change.kind()])
+                    .inc();
+            }
+            let removed = matches!(
+                &change,
+                Some(ShiftDiff::Updated(c) | ShiftDiff::TimeUpdated { critters: c, .. })
+                    if !c.unassigned.is_empty()
+            );
             match change {
                 Option::None => (),
                 Some(ShiftDiff::Created) => {
@@ -98,249 +290,451 @@ pub async fn start_event_processor(state: State) -> eyre::Result<()> {
                         .await
                         .wrap_err("create shift")?;
                 }
-                Some(ShiftDiff::Updated) => {
+                Some(ShiftDiff::Updated(critters)) => {
+                    let sequence = state.db.shift_sequence(shift.id).await?.unwrap_or_default();
                     state
                         .db
-                        .update_shift(shift)
+                        .update_shift(shift, &critter_diff_events(shift, critters, sequence))
                         .await
                         .wrap_err("update shift")?;
                 }
-                Some(ShiftDiff::TimeUpdated { old_start, old_end }) => {
+                Some(ShiftDiff::TimeUpdated {
+                    old_start,
+                    old_end,
+                    critters,
+                }) => {
+                    // reminders for the old time no longer count, the update deletes them
+                    sent.retain(|(id, _, _)| *id != shift.id);
+                    // the time change bumps the revision along with the update
+                    let sequence = state.db.shift_sequence(shift.id).await?.unwrap_or_default() + 1;
+                    // newly assigned critters never knew the old time
+                    let mut events = shift
+                        .critters
+                        .iter()
+                        .filter(|c| !critters.assigned.contains(&c.2))
+                        .map(|c| Event::UserTimeChanged {
+                            uid: c.2,
+                            shift: shift.clone(),
+                            old_start,
+                            old_end,
+                            sequence,
+                        })
+                        .collect::<Vec<_>>();
+                    events.extend(critter_diff_events(shift, critters, sequence));
                     state
                         .db
-                        .update_shift(shift)
+                        .update_shift(shift, &events)
                         .await
                         .wrap_err("update shift + time")?;
-                    for c in &shift.critters {
-                        tx.send(Event::UserTimeChanged {
-                            uid: c.2,
-                            shift: shift.clone(),
-                            old_start: old_start.with_timezone(&state.tz),
-                            old_end: old_end.with_timezone(&state.tz),
-                        });
-                    }
                 }
                 Some(ShiftDiff::Deleted) => {
+                    let sequence = state.db.shift_sequence(shift.id).await?.unwrap_or_default();
+                    let events = shift
+                        .critters
+                        .iter()
+                        .map(|c| Event::UserCanceled {
+                            uid: c.2,
+                            shift: shift.clone(),
+                            sequence: sequence + 1,
+                        })
+                        .collect::<Vec<_>>();
                     state
                         .db
-                        .delete_shift(shift.id)
+                        .delete_shift(shift.id, &events)
                         .await
                         .wrap_err("delete shift")?;
-                    for c in &shift.critters {
-                        tx.send(Event::UserCanceled {
-                            uid: c.2,
-                            shift: shift.clone(),
-                        });
-                    }
+                    deleted.push(shift.clone());
                     continue;
                 }
             }
-            if now.signed_duration_since(shift.start).num_minutes().abs() < 15
-                && !state.db.has_been_notified(shift.id).await?
-            {
-                for c in &shift.critters {
-                    tx.send(Event::UserUpcoming {
-                        uid: c.2,
+            let until = shift.start.signed_duration_since(now).num_minutes();
+            if until < 0 {
+                continue;
+            }
+            let short = shift.critters.len() < shift.req;
+            let alerted = understaffed.contains(&shift.id);
+            if short && !alerted && (removed || until <= i64::from(state.understaffed_window)) {
+                let events = staff(&state, shift)
+                    .into_iter()
+                    .map(|to| Event::Understaffed {
+                        to,
+                        shift: shift.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                state.db.set_understaffed(shift.id, true, &events).await?;
+            } else if !short && alerted {
+                let events = staff(&state, shift)
+                    .into_iter()
+                    .map(|to| Event::Staffed {
+                        to,
+                        shift: shift.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                state.db.set_understaffed(shift.id, false, &events).await?;
+            }
+            if !shift.managers.is_empty() && until <= i64::from(state.manager_lead) {
+                state
+                    .db
+                    .inform_managers(shift.id, |uid| Event::ManagerUpcoming {
+                        uid,
                         shift: shift.clone(),
-                    });
+                    })
+                    .await?;
+            }
+            let mut sequence = None;
+            for c in &shift.critters {
+                // every lead whose window has been entered counts as reminded, one message is enough
+                let due = leads
+                    .get(&c.2)
+                    .map_or(&DEFAULT_LEADS[..], Vec::as_slice)
+                    .iter()
+                    .copied()
+                    .filter(|lead| i64::from(*lead) >= until)
+                    .collect::<Vec<_>>();
+                if due
+                    .iter()
+                    .all(|lead| sent.contains(&(shift.id, c.2, *lead)))
+                {
+                    continue;
                 }
-                state.db.notify(shift.id, true).await?;
+                let sequence = match sequence {
+                    Some(sequence) => sequence,
+                    None => *sequence
+                        .insert(state.db.shift_sequence(shift.id).await?.unwrap_or_default()),
+                };
+                let event = Event::UserUpcoming {
+                    uid: c.2,
+                    shift: shift.clone(),
+                    lead: due.iter().copied().min().unwrap_or_default(),
+                    sequence,
+                };
+                state.db.remind(shift.id, c.2, &due, event).await?;
             }
         }
 
-        if let Some(false) = state
-            .db
-            .has_day_been_notified(now.with_timezone(&state.tz).date_naive())
-            .await?
+        if let Some(chat) = state.open_shifts_chat
+            && let Err(err) = board::sync_board(&state, chat, &new, &deleted).await
+        {
+            error!("Failed to sync open shift board: {err}");
+        }
+
+        if now.with_timezone(&state.tz).time() >= state.digest_time
+            && let Some(false) = state.db.has_day_been_notified(date).await?
         {
-            // TODO: inform user of daily stuff
+            trace!(date = date.to_string(), "sending daily digests...");
+            let events = daily_digests(&new, date, now)
+                .into_iter()
+                .map(|(uid, next)| Event::UserDaily { uid, next })
+                .collect::<Vec<_>>();
+            state.db.notify_day(date, true, &events).await?;
         }
 
-        sleep(Duration::from_secs(state.poll_interval as u64)).await;
+        timer.observe_duration();
+        metrics::LAST_POLL.set(Utc::now().timestamp());
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(state.poll_interval as u64)) => (),
+            _ = state.resync.notify.notified() => trace!("resync requested"),
+        }
     }
 }
 
-/// We do some early filtering here to not spawn unnececary task (not like tokio would care), but also to not overwhelm the db, not that this software will ever run on scale haha
-pub async fn distribute(state: State, mut stream: UnboundedReceiver<Event>) {
-    while let Some(event) = stream.recv().await {
-        let Some(cid) = crate::retry!(state.db.get_chat_id(event.target())) else {
-            continue;
-        };
-        let state = state.clone();
-        tokio::spawn(async move {
-            let Err(err) = handle_event(state, event, cid).await else {
-                return;
-            };
-            error!("{err}");
-        });
+/// Adds the stored and the current shifts of the given days
+async fn pull(
+    state: &State,
+    dates: &[NaiveDate],
+    old: &mut Vec<Shift>,
+    new: &mut Vec<Shift>,
+) -> eyre::Result<()> {
+    for date in dates {
+        trace!(date = date.to_string(), "syncing posts of the day...");
+        old.extend(
+            state
+                .db
+                .posts(*date, state.tz)
+                .await
+                .wrap_err("db posts pull")?,
+        );
+        new.extend(
+            state
+                .api
+                .shifts(*date, state.tz)
+                .await
+                .inspect_err(|_| metrics::API_ERRORS.with_label_values(&["shifts"]).inc())
+                .wrap_err("api posts")?,
+        );
     }
+    Ok(())
+}
+
+/// Stored shifts missing from the current ones, they were either deleted or moved to another day
+fn vanished<'a>(old: &'a [Shift], new: &'a [Shift]) -> impl Iterator<Item = i64> + 'a {
+    old.iter()
+        .map(|s| s.id)
+        .filter(|id| new.iter().all(|s| s.id != *id))
+}
+
+/// Current shifts missing from the stored ones, they were either created or moved from another day
+fn appeared(old: &[Shift], new: &[Shift]) -> Vec<i64> {
+    vanished(new, old).collect()
+}
+
+/// Everybody responsible for the staffing of a shift
+fn staff(state: &State, shift: &Shift) -> Vec<Recipient> {
+    shift
+        .managers
+        .iter()
+        .map(|m| Recipient::Critter(m.1))
+        .chain(state.staff_chat.map(Recipient::Chat))
+        .collect()
+}
+
+fn critter_diff_events(shift: &Shift, critters: CritterDiff, sequence: i32) -> Vec<Event> {
+    let assigned = critters
+        .assigned
+        .into_iter()
+        .map(|uid| Event::UserAssigned {
+            uid,
+            shift: shift.clone(),
+            sequence,
+        });
+    let unassigned = critters
+        .unassigned
+        .into_iter()
+        .map(|(role, uid)| Event::UserUnassigned {
+            uid,
+            role,
+            shift: shift.clone(),
+            sequence,
+        });
+    assigned.chain(unassigned).collect()
+}
+
+pub enum Delivery {
+    Sent,
+    Blocked,
 }
 
 #[tracing::instrument(name = "bot_event_send", skip(state, event))]
-pub async fn handle_event(state: State, event: Event, cid: ChatId) -> eyre::Result<()> {
-    let Err(err) = state.bot.send_message(cid, format!("{}", event)).await else {
+pub async fn handle_event(
+    state: &State,
+    event: &Event,
+    cid: ChatId,
+    lang: Lang,
+) -> eyre::Result<Delivery> {
+    let text = match state.templates.render(event, lang) {
+        Some(text) => text,
+        None => render::event(event, lang)?,
+    };
+    let file = calendar_file(state, event, lang);
+    let markup = match event {
+        Event::UserUpcoming { uid, shift, .. } => {
+            let picked = state.db.check_ins(shift.id).await?.get(uid).map(|a| a.0);
+            Some(bot::check_in_buttons(shift.id, picked, lang))
+        }
+        _ => None,
+    };
+    let Err(err) = send(state, cid, &text, file, markup).await else {
         trace!("send reminder");
-        return Ok(());
+        metrics::MESSAGES.with_label_values(&["sent"]).inc();
+        return Ok(Delivery::Sent);
     };
     if matches!(
         err,
         teloxide::RequestError::Api(teloxide::ApiError::BotBlocked)
     ) {
         trace!("bot is blocked");
-        return Ok(());
+        metrics::MESSAGES.with_label_values(&["blocked"]).inc();
+        metrics::BOT_BLOCKED.inc();
+        return Ok(Delivery::Blocked);
     };
+    metrics::MESSAGES.with_label_values(&["failed"]).inc();
 
     Err(err)?
 }
 
+/// Sends a message, attaching the file and buttons if there are any
+async fn send(
+    state: &State,
+    cid: ChatId,
+    text: &str,
+    file: Option<InputFile>,
+    markup: Option<InlineKeyboardMarkup>,
+) -> Result<(), teloxide::RequestError> {
+    if let Some(file) = &file
+        && text.chars().count() <= CAPTION_LIMIT
+    {
+        state
+            .sender
+            .request(cid, || {
+                let mut req = state
+                    .bot
+                    .send_document(cid, file.clone())
+                    .caption(text)
+                    .parse_mode(render::PARSE_MODE);
+                if let Some(markup) = &markup {
+                    req = req.reply_markup(markup.clone());
+                }
+                req.send()
+            })
+            .await?;
+        return Ok(());
+    }
+    // the file goes first, a retry after the text failed only repeats the file, which calendars apply just once
+    if let Some(file) = file {
+        state
+            .sender
+            .request(cid, || state.bot.send_document(cid, file.clone()).send())
+            .await?;
+    }
+    state
+        .sender
+        .request(cid, || {
+            let mut req = state
+                .bot
+                .send_message(cid, text)
+                .parse_mode(render::PARSE_MODE);
+            if let Some(markup) = &markup {
+                req = req.reply_markup(markup.clone());
+            }
+            req.send()
+        })
+        .await?;
+    Ok(())
+}
+
+/// Calendar file letting critters add or update the shift of an event in their calendar
+fn calendar_file(state: &State, event: &Event, lang: Lang) -> Option<InputFile> {
+    let (shift, role, sequence, canceled) = match event {
+        Event::UserUpcoming {
+            uid,
+            shift,
+            sequence,
+            ..
+        }
+        | Event::UserTimeChanged {
+            uid,
+            shift,
+            sequence,
+            ..
+        }
+        | Event::UserAssigned {
+            uid,
+            shift,
+            sequence,
+        } => (shift, render::role(shift, *uid), sequence, false),
+        Event::UserCanceled {
+            uid,
+            shift,
+            sequence,
+        } => (shift, render::role(shift, *uid), sequence, true),
+        Event::UserUnassigned {
+            role,
+            shift,
+            sequence,
+            ..
+        } => (shift, &**role, sequence, true),
+        _ => return None,
+    };
+
+    let mut calendar = Calendar::new(state.api.host());
+    calendar.event(Entry {
+        shift,
+        role,
+        lang,
+        url: state.api.shift_url(shift.id).ok(),
+        sequence: *sequence,
+        canceled,
+    });
+    let file = InputFile::memory(calendar.finish().into_bytes())
+        .file_name(format!("shift-{}.ics", shift.id));
+    Some(file)
+}
+
 impl Event {
-    fn target(&self) -> i64 {
+    /// Name of the variant, matching its serialized tag
+    pub fn kind(&self) -> &'static str {
         match self {
-            Event::UserUpcoming { uid, .. } => *uid,
-            Event::ManagerUpcoming { uid, .. } => *uid,
-            Event::UserDaily { uid, .. } => *uid,
-            Event::UserTimeChanged { uid, .. } => *uid,
-            Event::UserCanceled { uid, .. } => *uid,
+            Event::UserUpcoming { .. } => "user_upcoming",
+            Event::UserTimeChanged { .. } => "user_time_changed",
+            Event::UserCanceled { .. } => "user_canceled",
+            Event::UserAssigned { .. } => "user_assigned",
+            Event::UserUnassigned { .. } => "user_unassigned",
+            Event::ManagerUpcoming { .. } => "manager_upcoming",
+            Event::UserDaily { .. } => "user_daily",
+            Event::Understaffed { .. } => "understaffed",
+            Event::Staffed { .. } => "staffed",
+            Event::Broadcast { .. } => "broadcast",
         }
     }
-}
 
-impl Display for Event {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    pub fn target(&self) -> Recipient {
         match self {
-            Event::UserUpcoming { shift, uid } => {
-                writeln!(
-                    f,
-                    "**Upcoming shift:** {} ({}) as {}",
-                    shift.title,
-                    shift.r#type,
-                    shift.critters.iter().find(|c| c.2 == *uid).unwrap().1
-                )?;
-                writeln!(f, "Location: {}", shift.location)?;
-                writeln!(
-                    f,
-                    "Starts: {} (in {})",
-                    shift.start.with_timezone(&shift.tz),
-                    shift.start.signed_duration_since(&Utc::now())
-                )?;
-                writeln!(
-                    f,
-                    "Ends: {} ({} total)",
-                    shift.end.with_timezone(&shift.tz),
-                    shift.end.signed_duration_since(&shift.start)
-                )?;
-
-                if shift.ppe {
-                    writeln!(f, "**PPE is required**")?;
-                }
-
-                Ok(())
-            }
-            Event::ManagerUpcoming { shift, .. } => {
-                writeln!(f, "**Upcoming shift:** {} ({})", shift.title, shift.r#type)?;
-                writeln!(f, "Location: {}", shift.location)?;
-                writeln!(
-                    f,
-                    "Starts: {} (in {})",
-                    shift.start.with_timezone(&shift.start.timezone()),
-                    shift.start.signed_duration_since(&Utc::now())
-                )?;
-                writeln!(
-                    f,
-                    "Ends: {} ({} total)",
-                    shift.end.with_timezone(&shift.start.timezone()),
-                    shift.end.signed_duration_since(&shift.start)
-                )?;
-
-                if shift.ppe {
-                    writeln!(f, "**PPE is required**")?;
-                }
-
-                writeln!(
-                    f,
-                    "Assigned critters: ({}/{})",
-                    shift.critters.len(),
-                    shift.req
-                )?;
-                for (name, r#type, _, staff) in shift.critters.iter() {
-                    writeln!(
-                        f,
-                        "- {}{} as {}",
-                        name,
-                        if *staff { " (Staff)" } else { "" },
-                        r#type
-                    )?;
-                }
+            Event::UserUpcoming { uid, .. } => Recipient::Critter(*uid),
+            Event::ManagerUpcoming { uid, .. } => Recipient::Critter(*uid),
+            Event::UserDaily { uid, .. } => Recipient::Critter(*uid),
+            Event::UserTimeChanged { uid, .. } => Recipient::Critter(*uid),
+            Event::UserCanceled { uid, .. } => Recipient::Critter(*uid),
+            Event::UserAssigned { uid, .. } => Recipient::Critter(*uid),
+            Event::UserUnassigned { uid, .. } => Recipient::Critter(*uid),
+            Event::Understaffed { to, .. } => *to,
+            Event::Staffed { to, .. } => *to,
+            Event::Broadcast { uid, .. } => Recipient::Critter(*uid),
+        }
+    }
 
-                Ok(())
-            }
-            Event::UserDaily { next, uid } => {
-                writeln!(f, "Your shifts today: ")?;
-
-                for shift in next {
-                    writeln!(
-                        f,
-                        "- **{} ({}) as {}** @ {}: {} (in {}) -> {} ({} total){}",
-                        shift.title,
-                        shift.r#type,
-                        shift.critters.iter().find(|c| c.2 == *uid).unwrap().1,
-                        shift.location,
-                        shift.start.with_timezone(&shift.start.timezone()),
-                        shift.start.signed_duration_since(&Utc::now()),
-                        shift.end.with_timezone(&shift.start.timezone()),
-                        shift.end.signed_duration_since(&shift.start),
-                        if shift.ppe { " **[PPE]**" } else { "" }
-                    )?;
-                }
+    /// Idempotency key of the event, state transitions which may legitimately repeat carry the time they were detected at
+    pub fn key(&self, at: DateTime<Utc>) -> String {
+        let to = self.target();
+        let at = at.timestamp_millis();
+        match self {
+            Event::UserUpcoming { shift, lead, .. } => format!(
+                "upcoming:{}:{to}:{}:{lead}",
+                shift.id,
+                shift.start.timestamp()
+            ),
+            Event::ManagerUpcoming { shift, .. } => format!("manager-upcoming:{}:{to}", shift.id),
+            Event::UserDaily { next, .. } => format!(
+                "daily:{to}:{}",
+                next.first()
+                    .map(|s| s.start.with_timezone(&s.tz).date_naive().to_string())
+                    .unwrap_or_default()
+            ),
+            Event::UserTimeChanged { shift, .. } => format!(
+                "time-changed:{}:{to}:{}:{}",
+                shift.id,
+                shift.start.timestamp(),
+                shift.end.timestamp()
+            ),
+            Event::UserCanceled { shift, .. } => format!("canceled:{}:{to}", shift.id),
+            Event::UserAssigned { shift, .. } => format!("assigned:{}:{to}:{at}", shift.id),
+            Event::UserUnassigned { shift, .. } => format!("unassigned:{}:{to}:{at}", shift.id),
+            Event::Understaffed { shift, .. } => format!("understaffed:{}:{to}:{at}", shift.id),
+            Event::Staffed { shift, .. } => format!("staffed:{}:{to}:{at}", shift.id),
+            Event::Broadcast { id, .. } => format!("broadcast:{id}:{to}"),
+        }
+    }
+}
 
-                Ok(())
-            }
-            Event::UserTimeChanged {
-                uid,
-                shift,
-                old_start,
-                old_end,
-            } => {
-                writeln!(
-                    f,
-                    "**Starttime of shift changed:** {} ({}) as {}",
-                    shift.title,
-                    shift.r#type,
-                    shift.critters.iter().find(|c| c.2 == *uid).unwrap().1
-                )?;
-                writeln!(
-                    f,
-                    "Now Starts: **{} (in {})**, originally {}",
-                    shift.start.with_timezone(&shift.start.timezone()),
-                    shift.start.signed_duration_since(&Utc::now()),
-                    old_start.with_timezone(&shift.start.timezone()),
-                )?;
-                writeln!(
-                    f,
-                    "Ends: **{} ({} total)**, originally {}",
-                    shift.end.with_timezone(&shift.start.timezone()),
-                    shift.end.signed_duration_since(&shift.start),
-                    old_end.with_timezone(&shift.start.timezone()),
-                )?;
-
-                Ok(())
-            }
-            Event::UserCanceled { uid, shift } => {
-                writeln!(
-                    f,
-                    "**Shift canceled:** {} ({}) as {}",
-                    shift.title,
-                    shift.r#type,
-                    shift.critters.iter().find(|c| c.2 == *uid).unwrap().1
-                )?;
-                writeln!(
-                    f,
-                    "You no longer need to show up.\n\nIf you believe this was a mistake please contact the responsible shift manager."
-                )?;
-                Ok(())
-            }
+/// Shifts of the day per critter, leaving out those which are already over
+fn daily_digests(
+    shifts: &[Shift],
+    date: NaiveDate,
+    now: DateTime<Utc>,
+) -> HashMap<i64, Vec<Shift>> {
+    let mut digests = HashMap::<i64, Vec<Shift>>::new();
+    for shift in shifts
+        .iter()
+        .filter(|s| s.start.with_timezone(&s.tz).date_naive() == date && s.end > now)
+    {
+        for c in &shift.critters {
+            digests.entry(c.2).or_default().push(shift.clone());
         }
     }
+    for next in digests.values_mut() {
+        next.sort_by_key(|s| s.start);
+    }
+    digests
 }
 
 pub fn diff_shift(old: Option<&Shift>, new: Option<&Shift>) -> Option<ShiftDiff> {
@@ -354,13 +748,32 @@ pub fn diff_shift(old: Option<&Shift>, new: Option<&Shift>) -> Option<ShiftDiff>
     let Some(new) = new else {
         return Some(ShiftDiff::Deleted);
     };
+    let critters = diff_critters(old, new);
     if old.start != new.start || old.end != new.end {
         Some(ShiftDiff::TimeUpdated {
             old_start: old.start,
             old_end: old.end,
+            critters,
         })
     } else {
-        Some(ShiftDiff::Updated)
+        Some(ShiftDiff::Updated(critters))
+    }
+}
+
+fn diff_critters(old: &Shift, new: &Shift) -> CritterDiff {
+    CritterDiff {
+        assigned: new
+            .critters
+            .iter()
+            .filter(|c| !old.critters.iter().any(|o| o.2 == c.2))
+            .map(|c| c.2)
+            .collect(),
+        unassigned: old
+            .critters
+            .iter()
+            .filter(|c| !new.critters.iter().any(|n| n.2 == c.2))
+            .map(|c| (c.1.clone(), c.2))
+            .collect(),
     }
 }
 
@@ -370,18 +783,16 @@ fn scan_iter<'a>(
 ) -> impl Iterator<Item = (&'a Shift, Option<ShiftDiff>)> {
     let mut keys = HashSet::new();
     let old = old
-        .into_iter()
-        .map(|s| {
+        .iter()
+        .inspect(|s| {
             keys.insert(s.id);
-            s
         })
         .map(|s| (s.id, s))
         .collect::<HashMap<_, _>>();
     let new = new
-        .into_iter()
-        .map(|s| {
+        .iter()
+        .inspect(|s| {
             keys.insert(s.id);
-            s
         })
         .map(|s| (s.id, s))
         .collect::<HashMap<_, _>>();
@@ -391,6 +802,89 @@ fn scan_iter<'a>(
         let new = new.get(&id);
         let act = new.or(old).unwrap();
 
-        (*act, diff_shift(old.map(|s| *s), new.map(|s| *s)))
+        (*act, diff_shift(old.copied(), new.copied()))
     })
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Shift starting an hour from now with a single critter assigned
+    pub fn shift(id: i64) -> Shift {
+        let start = Utc::now() + TimeDelta::hours(1);
+        Shift {
+            id,
+            title: "Registration".into(),
+            r#type: "Registration desk".into(),
+            location: "Hall H".into(),
+            start,
+            end: start + TimeDelta::hours(2),
+            tz: chrono_tz::Europe::Berlin,
+            critters: vec![("Fluff".into(), "Angel".into(), 7, false)],
+            managers: vec![("Boss".into(), 9)],
+            req: 2,
+            ppe: false,
+        }
+    }
+
+    #[test]
+    fn critter_diff() {
+        let mut old = shift(1);
+        old.critters
+            .push(("Stays".into(), "Angel".into(), 8, false));
+        let mut new = old.clone();
+        new.critters.remove(0);
+        new.critters.push(("New".into(), "Angel".into(), 10, true));
+
+        let diff = diff_critters(&old, &new);
+        assert_eq!(diff.assigned, [10]);
+        assert_eq!(diff.unassigned, [("Angel".into(), 7)]);
+    }
+
+    #[test]
+    fn unchanged_critters() {
+        let old = shift(1);
+        let mut new = old.clone();
+        // a changed role alone is no reassignment
+        new.critters[0].1 = "Lead".into();
+        let diff = diff_critters(&old, &new);
+        assert!(diff.assigned.is_empty());
+        assert!(diff.unassigned.is_empty());
+    }
+
+    #[test]
+    fn digest_skips_finished_shifts() {
+        let now = "2026-08-20T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let at = |id, hours, critter| {
+            let mut shift = shift(id);
+            shift.start = now + TimeDelta::hours(hours);
+            shift.end = shift.start + TimeDelta::hours(2);
+            shift.critters[0].2 = critter;
+            shift
+        };
+        // over, running, upcoming and one which only had critter 8 on it
+        let shifts = [at(1, -3, 7), at(2, -1, 7), at(3, 2, 7), at(4, -4, 8)];
+
+        let digests = daily_digests(&shifts, now.date_naive(), now);
+        let next = digests[&7].iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(next, [2, 3]);
+        assert!(!digests.contains_key(&8));
+    }
+
+    #[test]
+    fn shift_moved_across_days() {
+        let stored = [shift(1)];
+        let mut moved = stored[0].clone();
+        moved.start += TimeDelta::days(1);
+        moved.end += TimeDelta::days(1);
+
+        // pulling the old day alone looks like a deletion
+        assert_eq!(vanished(&stored, &[]).collect::<Vec<_>>(), [1]);
+        // pulling the new day alone looks like a new shift
+        assert_eq!(appeared(&[], std::slice::from_ref(&moved)), [1]);
+
+        let (_, change) = scan_iter(&stored, &[moved]).next().unwrap();
+        assert!(matches!(change, Some(ShiftDiff::TimeUpdated { .. })));
+    }
+}
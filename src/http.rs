@@ -0,0 +1,207 @@
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract,
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use color_eyre::eyre;
+use hmac::{Hmac, Mac};
+use prometheus::TextEncoder;
+use sha2::Sha256;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tracing::{debug, error};
+
+use crate::{
+    State,
+    ics::{Calendar, Entry},
+    metrics, render,
+};
+
+const SIGNATURE_HEADER: &str = "x-signature-256";
+/// How long past shifts stay in the calendar feeds
+const FEED_HISTORY: TimeDelta = TimeDelta::days(30);
+
+pub async fn start_http(state: State, addr: SocketAddr) -> eyre::Result<()> {
+    let mut router = Router::new()
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(ready))
+        .route("/metrics", get(metrics))
+        .route("/calendar/{token}", get(calendar));
+    if state.webhook_secret.is_some() {
+        router = router.route("/webhook/shifts", post(shifts_changed));
+    }
+
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, router.with_state(state)).await?;
+    Ok(())
+}
+
+/// Ready as long as the database is reachable and polls keep going through
+async fn ready(extract::State(state): extract::State<State>) -> (StatusCode, &'static str) {
+    if let Err(err) = state.db.ping().await {
+        error!("Database is not reachable: {err}");
+        return (StatusCode::SERVICE_UNAVAILABLE, "database unreachable");
+    }
+    let since = Utc::now().timestamp() - metrics::LAST_POLL.get();
+    if since > 3 * i64::from(state.poll_interval) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "poll stale");
+    }
+    (StatusCode::OK, "ok")
+}
+
+async fn metrics() -> Result<String, StatusCode> {
+    TextEncoder::new()
+        .encode_to_string(&prometheus::gather())
+        .map_err(|err| {
+            error!("Failed to encode metrics: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Calendar feed of a critter, the token being the only thing protecting it
+#[tracing::instrument(name = "calendar_feed", skip_all)]
+async fn calendar(
+    extract::State(state): extract::State<State>,
+    extract::Path(token): extract::Path<String>,
+) -> Response {
+    let token = token.strip_suffix(".ics").unwrap_or(&token);
+    let feed = async {
+        let Some(uid) = state.db.calendar_owner(token).await? else {
+            return Ok(None);
+        };
+        let lang = state.db.critter_language(uid).await?.unwrap_or_default();
+        let shifts = state
+            .db
+            .critter_shifts(uid, Utc::now() - FEED_HISTORY, DateTime::<Utc>::MAX_UTC)
+            .await?;
+        // has to match the revision of the files sent along with notifications, or clients drop updates
+        let sequences = state
+            .db
+            .shift_sequences(&shifts.iter().map(|s| s.id).collect::<Vec<_>>())
+            .await?;
+
+        let mut calendar = Calendar::new(state.api.host()).name(lang.text().calendar_name);
+        for shift in &shifts {
+            calendar.event(Entry {
+                shift,
+                role: render::role(shift, uid),
+                lang,
+                url: state.api.shift_url(shift.id).ok(),
+                sequence: sequences.get(&shift.id).copied().unwrap_or_default(),
+                canceled: false,
+            });
+        }
+        eyre::Ok(Some(calendar.finish()))
+    };
+    match feed.await {
+        Ok(Some(feed)) => ([(CONTENT_TYPE, "text/calendar; charset=utf-8")], feed).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            error!("Failed to build calendar feed: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ShiftsChanged {
+    date: Option<NaiveDate>,
+    shift_id: Option<i64>,
+}
+
+/// Called by the crittersystem whenever shifts change, the body is signed using HMAC-SHA256
+#[tracing::instrument(name = "webhook_shifts", skip_all)]
+async fn shifts_changed(
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let Some(secret) = &state.webhook_secret else {
+        return StatusCode::NOT_FOUND;
+    };
+    if !verify(secret, &headers, &body) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let Ok(Json(changed)) = Json::<ShiftsChanged>::from_bytes(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    debug!(date = ?changed.date, shift = ?changed.shift_id, "shifts changed");
+
+    let date = match (changed.date, changed.shift_id) {
+        (Some(date), _) => Some(date),
+        (None, Some(id)) => match state.db.shift_start(id).await {
+            Ok(start) => start.map(|start| start.with_timezone(&state.tz).date_naive()),
+            Err(err) => {
+                error!("Failed to look up shift {id}: {err}");
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        },
+        (None, None) => None,
+    };
+    match date {
+        Some(date) => state.resync.date(date),
+        // unknown shifts may have been created anywhere
+        None => state.resync.all(),
+    }
+
+    StatusCode::ACCEPTED
+}
+
+/// Checks the signature header against the HMAC-SHA256 of the body
+fn verify(secret: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+    let Some(signature) = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|sig| sig.to_str().ok())
+        .and_then(|sig| sig.strip_prefix("sha256="))
+        .and_then(decode_hex)
+    else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed(signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn verifies_signature() {
+        // echo -n '{"date":"2025-09-03"}' | openssl dgst -sha256 -hmac secret
+        let body = br#"{"date":"2025-09-03"}"#;
+        let hex = "9e79e500e0b694d921008a9d39bba81c7396a0c1d836f0994c201da8d4e45fc6";
+
+        assert!(verify("secret", &signed(&format!("sha256={hex}")), body));
+        assert!(!verify("other", &signed(&format!("sha256={hex}")), body));
+        assert!(!verify("secret", &signed(&format!("sha256={hex}")), b"{}"));
+        assert!(!verify("secret", &signed(hex), body));
+        assert!(!verify("secret", &HeaderMap::new(), body));
+    }
+}
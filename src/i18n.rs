@@ -0,0 +1,386 @@
+use std::fmt::Display;
+
+/// Languages we have message catalogues for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    De,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::En, Lang::De];
+
+    /// Picks the catalogue for a telegram language code such as `de` or `en-US`
+    pub fn from_code(code: &str) -> Option<Lang> {
+        let code = code.split(['-', '_']).next()?.to_ascii_lowercase();
+        Lang::ALL.into_iter().find(|lang| lang.code() == code)
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::De => "de",
+        }
+    }
+
+    pub fn text(self) -> &'static Text {
+        match self {
+            Lang::En => &EN,
+            Lang::De => &DE,
+        }
+    }
+}
+
+/// Fills the `{name}` placeholders of a catalogue entry, unknown placeholders are left alone
+pub fn fill(template: &str, vars: &[(&str, &dyn Display)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let (_, value) = vars.iter().find(|(name, _)| *name == &rest[1..end])?;
+            Some((end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                out.push_str(&value.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Every user facing string of the bot in a single language
+pub struct Text {
+    /// Name of the language in the language itself
+    pub name: &'static str,
+
+    pub help: &'static str,
+    pub cmd_start: &'static str,
+    pub cmd_help: &'static str,
+    pub cmd_myshifts: &'static str,
+    pub cmd_settings: &'static str,
+    pub cmd_unlink: &'static str,
+    pub cmd_language: &'static str,
+    pub cmd_calendar: &'static str,
+    pub cmd_roster: &'static str,
+
+    pub login_hint: &'static str,
+    pub not_linked: &'static str,
+    pub not_linked_yet: &'static str,
+    pub unlinked: &'static str,
+    pub already_linked: &'static str,
+    pub need_username: &'static str,
+    pub malformed_token: &'static str,
+    pub linked_elsewhere: &'static str,
+    pub linked: &'static str,
+    pub unknown_command: &'static str,
+
+    pub no_shifts: &'static str,
+    /// `{date}`
+    pub shifts_on: &'static str,
+    pub previous: &'static str,
+    pub next: &'static str,
+
+    pub no_reminders: &'static str,
+    /// `{leads}`
+    pub reminders: &'static str,
+    pub toggle_hint: &'static str,
+
+    pub language_prompt: &'static str,
+    pub language_auto: &'static str,
+    /// `{language}`
+    pub language_set: &'static str,
+
+    /// `{url}`
+    pub calendar: &'static str,
+    pub calendar_unavailable: &'static str,
+    pub calendar_name: &'static str,
+
+    pub on_my_way: &'static str,
+    pub checked_in: &'static str,
+    pub cant_make_it: &'static str,
+    pub no_answer: &'static str,
+    pub check_ins: &'static str,
+    /// `{id}`
+    pub roster_hint: &'static str,
+    pub roster_usage: &'static str,
+    pub unknown_shift: &'static str,
+    pub not_a_manager: &'static str,
+    pub not_permitted: &'static str,
+
+    pub upcoming: &'static str,
+    pub time_changed: &'static str,
+    pub canceled: &'static str,
+    pub assigned: &'static str,
+    pub unassigned: &'static str,
+    pub understaffed: &'static str,
+    pub staffed: &'static str,
+    pub today: &'static str,
+    pub no_show: &'static str,
+    pub broadcast: &'static str,
+
+    pub open_shift: &'static str,
+    pub started: &'static str,
+    pub filled: &'static str,
+    pub closed: &'static str,
+    pub sign_up: &'static str,
+
+    /// `{title}`, `{type}` and `{role}`
+    pub shift_as: &'static str,
+    /// `{name}` and `{role}`
+    pub member_as: &'static str,
+    pub location: &'static str,
+    pub starts: &'static str,
+    pub now_starts: &'static str,
+    pub ends: &'static str,
+    /// `{time}`
+    pub originally: &'static str,
+    /// `{span}`
+    pub total: &'static str,
+    pub ppe: &'static str,
+    /// Short form of [`Text::ppe`] for single line summaries
+    pub ppe_short: &'static str,
+    pub assigned_critters: &'static str,
+    pub free_spots: &'static str,
+    pub staff: &'static str,
+
+    /// Monday first
+    pub weekdays: [&'static str; 7],
+    /// chrono format string of dates
+    pub date: &'static str,
+    pub days: &'static str,
+    pub hours: &'static str,
+    pub minutes: &'static str,
+    pub under_a_minute: &'static str,
+    /// `{span}`
+    pub from_now: &'static str,
+    /// `{span}`
+    pub ago: &'static str,
+}
+
+static EN: Text = Text {
+    name: "English",
+
+    help: "These commands are supported:",
+    cmd_start: "link your critter account, use the link provided by the web interface",
+    cmd_help: "show this help",
+    cmd_myshifts: "list your upcoming shifts",
+    cmd_settings: "change your notification settings",
+    cmd_unlink: "unlink your critter account from this chat",
+    cmd_language: "change the language of the bot",
+    cmd_calendar: "get a calendar feed of your shifts",
+    cmd_roster: "see who checked in for a shift you manage",
+
+    login_hint: "Try logging in via the web interface https://critter.eurofurence.org/",
+    not_linked: "Your account is not linked.",
+    not_linked_yet: "Your account is not linked yet.",
+    unlinked: "Your account has been unlinked, you will no longer receive any notifications.\nYou can link it again at any time via the web interface https://critter.eurofurence.org/",
+    already_linked: "Your account is already linked.\nSee /help for what I can do for you.",
+    need_username: "Please set a telegram username before linking your account.",
+    malformed_token: "Malformed token provided.",
+    linked_elsewhere: "Your critter account has been linked to another telegram account, this chat will no longer receive any notifications.",
+    linked: "Your account has been linked successfully!\nFrom now on you will receive notification on any of your upcoming shifts.\nThank you for helping us out!",
+    unknown_command: "Unknown command provided.",
+
+    no_shifts: "You have no upcoming shifts.",
+    shifts_on: "Your shifts on {date}:",
+    previous: "« previous",
+    next: "next »",
+
+    no_reminders: "You will not be reminded of your upcoming shifts.",
+    reminders: "You will be reminded {leads} before each of your shifts.",
+    toggle_hint: "Tap a reminder to toggle it.",
+
+    language_prompt: "Which language should I talk to you in?",
+    language_auto: "Same as telegram",
+    language_set: "I will talk to you in {language} from now on.",
+
+    calendar: "Subscribe to this link in your calendar app to always have your shifts at hand:\n{url}\n\nKeep it to yourself, anyone with the link can see your shifts.",
+    calendar_unavailable: "Calendar feeds are not available right now.",
+    calendar_name: "Critter shifts",
+
+    on_my_way: "I'm on my way",
+    checked_in: "Checked in",
+    cant_make_it: "Can't make it",
+    no_answer: "no answer yet",
+    check_ins: "Check-ins",
+    roster_hint: "See who checked in via <code>/roster {id}</code>",
+    roster_usage: "Usage: /roster <shift id>",
+    unknown_shift: "There is no such shift.",
+    not_a_manager: "You do not manage this shift.",
+    not_permitted: "You are not permitted to use this command.",
+
+    upcoming: "Upcoming shift",
+    time_changed: "Starttime of shift changed",
+    canceled: "Shift canceled",
+    assigned: "Assigned to shift",
+    unassigned: "Removed from shift",
+    understaffed: "Understaffed shift",
+    staffed: "Shift fully staffed again",
+    today: "Your shifts today:",
+    no_show: "You no longer need to show up.\n\nIf you believe this was a mistake please contact the responsible shift manager.",
+    broadcast: "Message from the critter staff",
+
+    open_shift: "Open shift",
+    started: "Started",
+    filled: "Filled",
+    closed: "Canceled",
+    sign_up: "Sign up",
+
+    shift_as: "{title} ({type}) as {role}",
+    member_as: "{name} as {role}",
+    location: "Location",
+    starts: "Starts",
+    now_starts: "Now starts",
+    ends: "Ends",
+    originally: "originally {time}",
+    total: "{span} total",
+    ppe: "PPE is required",
+    ppe_short: "PPE",
+    assigned_critters: "Assigned critters",
+    free_spots: "Free spots",
+    staff: "Staff",
+
+    weekdays: ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+    date: "%Y-%m-%d",
+    days: "d",
+    hours: "h",
+    minutes: "min",
+    under_a_minute: "less than a minute",
+    from_now: "in {span}",
+    ago: "{span} ago",
+};
+
+static DE: Text = Text {
+    name: "Deutsch",
+
+    help: "Diese Befehle werden unterstützt:",
+    cmd_start: "verknüpfe deinen Critter-Account über den Link aus der Weboberfläche",
+    cmd_help: "zeige diese Hilfe",
+    cmd_myshifts: "liste deine kommenden Schichten auf",
+    cmd_settings: "ändere deine Benachrichtigungseinstellungen",
+    cmd_unlink: "trenne deinen Critter-Account von diesem Chat",
+    cmd_language: "ändere die Sprache des Bots",
+    cmd_calendar: "erhalte einen Kalender-Feed deiner Schichten",
+    cmd_roster: "sieh nach, wer für eine deiner geleiteten Schichten eingecheckt hat",
+
+    login_hint: "Melde dich über die Weboberfläche https://critter.eurofurence.org/ an.",
+    not_linked: "Dein Account ist nicht verknüpft.",
+    not_linked_yet: "Dein Account ist noch nicht verknüpft.",
+    unlinked: "Dein Account wurde getrennt, du erhältst keine Benachrichtigungen mehr.\nDu kannst ihn jederzeit über die Weboberfläche https://critter.eurofurence.org/ erneut verknüpfen.",
+    already_linked: "Dein Account ist bereits verknüpft.\nUnter /help siehst du, was ich für dich tun kann.",
+    need_username: "Bitte lege einen Telegram-Benutzernamen fest, bevor du deinen Account verknüpfst.",
+    malformed_token: "Ungültiges Token.",
+    linked_elsewhere: "Dein Critter-Account wurde mit einem anderen Telegram-Account verknüpft, dieser Chat erhält keine Benachrichtigungen mehr.",
+    linked: "Dein Account wurde erfolgreich verknüpft!\nAb jetzt wirst du über alle deine kommenden Schichten benachrichtigt.\nDanke für deine Hilfe!",
+    unknown_command: "Unbekannter Befehl.",
+
+    no_shifts: "Du hast keine kommenden Schichten.",
+    shifts_on: "Deine Schichten am {date}:",
+    previous: "« zurück",
+    next: "weiter »",
+
+    no_reminders: "Du wirst nicht an deine kommenden Schichten erinnert.",
+    reminders: "Du wirst {leads} vor jeder deiner Schichten erinnert.",
+    toggle_hint: "Tippe auf eine Erinnerung, um sie an- oder abzuschalten.",
+
+    language_prompt: "In welcher Sprache soll ich mit dir reden?",
+    language_auto: "Wie Telegram",
+    language_set: "Ab jetzt rede ich {language} mit dir.",
+
+    calendar: "Abonniere diesen Link in deiner Kalender-App, um deine Schichten immer im Blick zu haben:\n{url}\n\nBehalte ihn für dich, jeder mit dem Link kann deine Schichten sehen.",
+    calendar_unavailable: "Kalender-Feeds sind gerade nicht verfügbar.",
+    calendar_name: "Critter-Schichten",
+
+    on_my_way: "Bin unterwegs",
+    checked_in: "Eingecheckt",
+    cant_make_it: "Schaffe es nicht",
+    no_answer: "noch keine Antwort",
+    check_ins: "Check-ins",
+    roster_hint: "Wer eingecheckt hat, siehst du mit <code>/roster {id}</code>",
+    roster_usage: "Verwendung: /roster <Schicht-ID>",
+    unknown_shift: "Diese Schicht gibt es nicht.",
+    not_a_manager: "Du leitest diese Schicht nicht.",
+    not_permitted: "Du darfst diesen Befehl nicht verwenden.",
+
+    upcoming: "Anstehende Schicht",
+    time_changed: "Startzeit der Schicht geändert",
+    canceled: "Schicht abgesagt",
+    assigned: "Für Schicht eingetragen",
+    unassigned: "Von Schicht ausgetragen",
+    understaffed: "Unterbesetzte Schicht",
+    staffed: "Schicht wieder voll besetzt",
+    today: "Deine Schichten heute:",
+    no_show: "Du musst nicht mehr erscheinen.\n\nFalls das ein Fehler ist, wende dich bitte an die zuständige Schichtleitung.",
+    broadcast: "Nachricht vom Critter-Staff",
+
+    open_shift: "Offene Schicht",
+    started: "Begonnen",
+    filled: "Besetzt",
+    closed: "Abgesagt",
+    sign_up: "Eintragen",
+
+    shift_as: "{title} ({type}) als {role}",
+    member_as: "{name} als {role}",
+    location: "Ort",
+    starts: "Beginn",
+    now_starts: "Neuer Beginn",
+    ends: "Ende",
+    originally: "ursprünglich {time}",
+    total: "{span} insgesamt",
+    ppe: "Schutzausrüstung erforderlich",
+    ppe_short: "PSA",
+    assigned_critters: "Eingetragene Critter",
+    free_spots: "Freie Plätze",
+    staff: "Staff",
+
+    weekdays: ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+    date: "%d.%m.%Y",
+    days: "T",
+    hours: "Std",
+    minutes: "Min",
+    under_a_minute: "weniger als einer Minute",
+    from_now: "in {span}",
+    ago: "vor {span}",
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(
+            fill(
+                "{title} ({type}) as {role}",
+                &[("title", &"Desk"), ("type", &"Registration"), ("role", &1)]
+            ),
+            "Desk (Registration) as 1"
+        );
+        assert_eq!(fill("{a}{a}", &[("a", &"x")]), "xx");
+    }
+
+    #[test]
+    fn leaves_unknown_placeholders() {
+        assert_eq!(fill("{unknown} {", &[("a", &"x")]), "{unknown} {");
+        assert_eq!(fill("{{a}}", &[("a", &"x")]), "{x}");
+        // values are not filled again
+        assert_eq!(fill("{a}", &[("a", &"{b}"), ("b", &"y")]), "{b}");
+    }
+
+    #[test]
+    fn language_codes() {
+        assert_eq!(Lang::from_code("de-AT"), Some(Lang::De));
+        assert_eq!(Lang::from_code("EN_us"), Some(Lang::En));
+        assert_eq!(Lang::from_code("fr"), None);
+    }
+}
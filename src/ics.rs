@@ -0,0 +1,239 @@
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+
+use crate::{
+    events::Shift,
+    i18n::{Lang, fill},
+};
+
+const PRODID: &str = "-//Eurofurence//critter-bot//EN";
+/// Longest a content line may get in octets before it has to be folded
+const LINE_LIMIT: usize = 75;
+
+/// A single shift of a critter as calendar event
+pub struct Entry<'a> {
+    pub shift: &'a Shift,
+    pub role: &'a str,
+    pub lang: Lang,
+    pub url: Option<Url>,
+    /// Revision of the entry, clients only apply updates with a higher one
+    pub sequence: i32,
+    pub canceled: bool,
+}
+
+/// iCalendar object as described in RFC 5545.
+/// Times are written in UTC, so clients never have to rely on timezone definitions of ours.
+/// One-off files are plain publish-style objects as well, clients match them up with an entry they
+/// already know through its UID and only apply them if their SEQUENCE is higher.
+pub struct Calendar {
+    /// Domain the event UIDs are scoped to
+    domain: String,
+    name: Option<String>,
+    events: String,
+}
+
+impl Calendar {
+    pub fn new(domain: &str) -> Self {
+        Self {
+            domain: domain.into(),
+            name: None,
+            events: String::new(),
+        }
+    }
+
+    /// Name calendar apps show for a subscribed feed
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn event(&mut self, entry: Entry) {
+        let Entry {
+            shift,
+            role,
+            lang,
+            url,
+            sequence,
+            canceled,
+        } = entry;
+        let t = lang.text();
+
+        let mut description = fill(
+            t.shift_as,
+            &[
+                ("title", &shift.title),
+                ("type", &shift.r#type),
+                ("role", &role),
+            ],
+        );
+        if shift.ppe {
+            description.push('\n');
+            description.push_str(t.ppe);
+        }
+
+        let out = &mut self.events;
+        line(out, "BEGIN", "VEVENT");
+        line(out, "UID", &format!("shift-{}@{}", shift.id, self.domain));
+        line(out, "DTSTAMP", &utc(Utc::now()));
+        line(out, "SEQUENCE", &sequence.to_string());
+        if canceled {
+            line(out, "STATUS", "CANCELLED");
+        }
+        line(out, "DTSTART", &utc(shift.start));
+        line(out, "DTEND", &utc(shift.end));
+        line(
+            out,
+            "SUMMARY",
+            &escape(&format!("{} ({})", shift.title, shift.r#type)),
+        );
+        line(out, "LOCATION", &escape(&shift.location));
+        line(out, "DESCRIPTION", &escape(&description));
+        if let Some(url) = url {
+            line(out, "URL", url.as_str());
+        }
+        line(out, "END", "VEVENT");
+    }
+
+    pub fn finish(self) -> String {
+        let mut out = String::new();
+        line(&mut out, "BEGIN", "VCALENDAR");
+        line(&mut out, "VERSION", "2.0");
+        line(&mut out, "PRODID", PRODID);
+        line(&mut out, "CALSCALE", "GREGORIAN");
+        if let Some(name) = &self.name {
+            line(&mut out, "X-WR-CALNAME", &escape(name));
+        }
+        out.push_str(&self.events);
+        line(&mut out, "END", "VCALENDAR");
+        out
+    }
+}
+
+fn utc(at: DateTime<Utc>) -> String {
+    at.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escapes a TEXT value
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Writes a content line, folding it once it gets too long
+fn line(out: &mut String, name: &str, value: &str) {
+    let mut len = 0;
+    for c in name.chars().chain([':']).chain(value.chars()) {
+        if len + c.len_utf8() > LINE_LIMIT {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        len += c.len_utf8();
+        out.push(c);
+    }
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::shift;
+    use chrono::{NaiveDateTime, TimeZone};
+    use chrono_tz::Europe::Berlin;
+
+    /// Content lines of the output with the folding undone
+    fn unfold(ics: &str) -> Vec<String> {
+        ics.replace("\r\n ", "")
+            .split("\r\n")
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn value<'a>(lines: &'a [String], name: &str) -> &'a str {
+        lines
+            .iter()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+            .unwrap()
+    }
+
+    #[test]
+    fn shift_across_dst_change() {
+        let mut shift = shift(1);
+        // clocks go back from 03:00 CEST to 02:00 CET in between
+        shift.start = Berlin
+            .with_ymd_and_hms(2025, 10, 26, 1, 30, 0)
+            .unwrap()
+            .to_utc();
+        shift.end = Berlin
+            .with_ymd_and_hms(2025, 10, 26, 3, 30, 0)
+            .unwrap()
+            .to_utc();
+        let mut calendar = Calendar::new("critter.example.org");
+        calendar.event(Entry {
+            shift: &shift,
+            role: "Angel",
+            lang: Lang::En,
+            url: None,
+            sequence: 0,
+            canceled: false,
+        });
+        let lines = unfold(&calendar.finish());
+
+        let parse = |name| {
+            NaiveDateTime::parse_from_str(value(&lines, name), "%Y%m%dT%H%M%SZ")
+                .unwrap()
+                .and_utc()
+        };
+        assert_eq!(parse("DTSTART"), shift.start);
+        assert_eq!(parse("DTEND"), shift.end);
+        assert_eq!(shift.end - shift.start, chrono::TimeDelta::hours(3));
+    }
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(escape("a,b;c\\d\r\ne"), "a\\,b\\;c\\\\d\\ne");
+    }
+
+    #[test]
+    fn folds_long_lines() {
+        let mut out = String::new();
+        line(&mut out, "SUMMARY", &"ä".repeat(100));
+        let lines = out
+            .trim_end_matches("\r\n")
+            .split("\r\n")
+            .collect::<Vec<_>>();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|l| l.len() <= LINE_LIMIT));
+        assert!(lines[1..].iter().all(|l| l.starts_with(' ')));
+        assert_eq!(unfold(&out)[0], format!("SUMMARY:{}", "ä".repeat(100)));
+    }
+
+    #[test]
+    fn cancellation_without_method() {
+        let shift = shift(1);
+        let mut calendar = Calendar::new("critter.example.org");
+        calendar.event(Entry {
+            shift: &shift,
+            role: "Angel",
+            lang: Lang::En,
+            url: None,
+            sequence: 2,
+            canceled: true,
+        });
+        let lines = unfold(&calendar.finish());
+        assert_eq!(value(&lines, "UID"), "shift-1@critter.example.org");
+        assert_eq!(value(&lines, "SEQUENCE"), "2");
+        assert_eq!(value(&lines, "STATUS"), "CANCELLED");
+        assert!(!lines.iter().any(|l| l.starts_with("METHOD")));
+    }
+}
@@ -1,23 +1,35 @@
-use crate::{api::Api, db::Database};
+use crate::{api::Api, db::Database, events::Resync, sender::Sender, templates::Templates};
+use chrono::NaiveTime;
 use chrono_tz::Tz;
 use clap::{
     Arg, ArgAction, Command,
     builder::{RangedU64ValueParser, StringValueParser},
 };
 use color_eyre::eyre;
-use reqwest::{
-    Client, ClientBuilder, Url,
-    header::{AUTHORIZATION, HeaderMap, HeaderValue},
-};
+use reqwest::Url;
 use sqlx::PgPool;
-use std::{fs, sync::Arc};
-use teloxide::Bot;
+use std::{fs, net::SocketAddr, path::PathBuf, sync::Arc};
+use teloxide::{
+    Bot,
+    types::{ChatId, UserId},
+    update_listeners::webhooks,
+};
 use tracing_subscriber::EnvFilter;
 
 mod api;
+mod board;
 mod bot;
+mod broadcast;
 mod db;
 mod events;
+mod http;
+mod i18n;
+mod ics;
+mod metrics;
+mod outbox;
+mod render;
+mod sender;
+mod templates;
 
 #[derive(Clone)]
 pub struct State {
@@ -26,6 +38,21 @@ pub struct State {
     db: Database,
     tz: Tz,
     poll_interval: u32,
+    future_poll_interval: u32,
+    sync_days: u32,
+    digest_time: NaiveTime,
+    manager_lead: u32,
+    understaffed_window: u32,
+    staff_chat: Option<ChatId>,
+    open_shifts_chat: Option<ChatId>,
+    open_shifts_window: u32,
+    webhook_secret: Option<Arc<str>>,
+    resync: Resync,
+    sender: Sender,
+    templates: Templates,
+    calendar_url: Option<Url>,
+    admin_critters: Arc<[i64]>,
+    admin_users: Arc<[UserId]>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -106,6 +133,149 @@ async fn main() -> eyre::Result<()> {
                 .default_value("60")
                 .value_parser(RangedU64ValueParser::<u32>::new())
         )
+        .arg(
+            Arg::new("future-pollint")
+                .env("FUTURE_POLLINT")
+                .long("future-pollint")
+                .help("Interval between every poll of upcoming event days in seconds, today is polled using `pollint`")
+                .default_value("900")
+                .value_parser(RangedU64ValueParser::<u32>::new())
+        )
+        .arg(
+            Arg::new("sync-days")
+                .env("SYNC_DAYS")
+                .long("sync-days")
+                .help("Amount of days after today to keep in sync with the critter server")
+                .default_value("3")
+                .value_parser(RangedU64ValueParser::<u32>::new())
+        )
+        .arg(
+            Arg::new("digest-time")
+                .env("DIGEST_TIME")
+                .long("digest-time")
+                .help("Local time in the events timezone at which every critter receives their daily shift digest, e.g. `08:00`")
+                .default_value("08:00")
+                .value_parser(clap::value_parser!(NaiveTime))
+        )
+        .arg(
+            Arg::new("manager-lead")
+                .env("MANAGER_LEAD")
+                .long("manager-lead")
+                .help("Minutes before a shift at which its managers receive the roster")
+                .default_value("30")
+                .value_parser(RangedU64ValueParser::<u32>::new())
+        )
+        .arg(
+            Arg::new("understaffed-window")
+                .env("UNDERSTAFFED_WINDOW")
+                .long("understaffed-window")
+                .help("Minutes before a shift from which on managers get alerted about it lacking critters")
+                .default_value("120")
+                .value_parser(RangedU64ValueParser::<u32>::new())
+        )
+        .arg(
+            Arg::new("staff-chat")
+                .env("STAFF_CHAT")
+                .long("staff-chat")
+                .help("Telegram chat id of the staff group which receives staffing alerts")
+                .value_parser(clap::value_parser!(i64))
+        )
+        .arg(
+            Arg::new("admin-critters")
+                .env("ADMIN_CRITTERS")
+                .long("admin-critters")
+                .help("Comma separated critter ids of the admins, they have to have linked their telegram account")
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(i64))
+        )
+        .arg(
+            Arg::new("admin-tg-ids")
+                .env("ADMIN_TELEGRAM_IDS")
+                .long("admin-tg-ids")
+                .help("Comma separated telegram user ids of the admins, they have to have linked their critter account")
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("open-shifts-chat")
+                .env("OPEN_SHIFTS_CHAT")
+                .long("open-shifts-chat")
+                .help("Telegram channel or group id to post unfilled shifts to, disabled if not set")
+                .value_parser(clap::value_parser!(i64))
+        )
+        .arg(
+            Arg::new("open-shifts-window")
+                .env("OPEN_SHIFTS_WINDOW")
+                .long("open-shifts-window")
+                .help("Minutes before a shift from which on it gets posted as open shift while unfilled")
+                .default_value("180")
+                .value_parser(RangedU64ValueParser::<u32>::new())
+        )
+        .arg(
+            Arg::new("http-listen")
+                .env("HTTP_LISTEN")
+                .long("http-listen")
+                .help("Address to serve the http endpoints on, e.g. `0.0.0.0:8080`, disabled if not set")
+                .value_parser(clap::value_parser!(SocketAddr))
+        )
+        .arg(
+            Arg::new("calendar-url")
+                .env("CALENDAR_URL")
+                .long("calendar-url")
+                .help("Public url the http endpoints are reachable at, e.g. `https://bot.example.org/`, enables calendar feeds")
+                .requires("http-listen")
+                .value_parser(clap::value_parser!(Url))
+        )
+        .arg(
+            Arg::new("webhook-secret")
+                .env("WEBHOOK_SECRET")
+                .long("webhook-secret")
+                .help("Secret the crittersystem signs its shift change webhook calls with, the webhook is disabled if not set")
+                .requires("http-listen")
+                .value_parser(StringValueParser::new())
+        )
+        .arg(
+            Arg::new("templates")
+                .env("TEMPLATES")
+                .long("templates")
+                .help("Directory of message templates overriding the built in notifications, reloaded on SIGHUP")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("tg-webhook-listen")
+                .env("TELEGRAM_WEBHOOK_LISTEN")
+                .long("tg-webhook-listen")
+                .help("Address to receive telegram updates on, e.g. `0.0.0.0:8443`, long polling is used if not set")
+                .requires("tg-webhook-url")
+                .value_parser(clap::value_parser!(SocketAddr))
+        )
+        .arg(
+            Arg::new("tg-webhook-url")
+                .env("TELEGRAM_WEBHOOK_URL")
+                .long("tg-webhook-url")
+                .help("Public url telegram sends updates to, e.g. `https://bot.example.org/telegram`")
+                .requires("tg-webhook-listen")
+                .value_parser(clap::value_parser!(Url))
+        )
+        .arg(
+            Arg::new("tg-webhook-secret")
+                .env("TELEGRAM_WEBHOOK_SECRET")
+                .long("tg-webhook-secret")
+                .help("Secret token telegram sends along with every update, generated on start if not set")
+                .value_parser(|token: &str| {
+                    if (1..=256).contains(&token.len())
+                        && token
+                            .bytes()
+                            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+                    {
+                        Ok(token.to_string())
+                    } else {
+                        Err("must be 1-256 characters of `A-Z`, `a-z`, `0-9`, `_` and `-`")
+                    }
+                })
+        )
         .arg(
             Arg::new("pq-lim")
                 .env("PARALLEL_LOOKUP_LIMIT")
@@ -115,6 +285,10 @@ async fn main() -> eyre::Result<()> {
         )
         .get_matches();
 
+    metrics::init();
+    // fail early on broken templates instead of once the first notification goes out
+    let templates = Templates::load(matches.get_one::<PathBuf>("templates").cloned())?;
+
     let pool = PgPool::connect(matches.get_one::<String>("pool").unwrap().as_str()).await?;
 
     if !matches.get_flag("no-migrate") {
@@ -131,10 +305,61 @@ async fn main() -> eyre::Result<()> {
         db: Database::new(pool, pq_limit),
         tz: *matches.get_one("timezone").unwrap(),
         poll_interval: *matches.get_one::<u32>("pollint").unwrap(),
+        future_poll_interval: *matches.get_one::<u32>("future-pollint").unwrap(),
+        sync_days: *matches.get_one::<u32>("sync-days").unwrap(),
+        digest_time: *matches.get_one::<NaiveTime>("digest-time").unwrap(),
+        manager_lead: *matches.get_one::<u32>("manager-lead").unwrap(),
+        understaffed_window: *matches.get_one::<u32>("understaffed-window").unwrap(),
+        staff_chat: matches.get_one::<i64>("staff-chat").copied().map(ChatId),
+        open_shifts_chat: matches
+            .get_one::<i64>("open-shifts-chat")
+            .copied()
+            .map(ChatId),
+        open_shifts_window: *matches.get_one::<u32>("open-shifts-window").unwrap(),
+        webhook_secret: matches
+            .get_one::<String>("webhook-secret")
+            .map(|s| Arc::from(s.as_str())),
+        resync: Resync::default(),
+        sender: Sender::default(),
+        templates,
+        calendar_url: matches.get_one::<Url>("calendar-url").cloned(),
+        admin_critters: matches
+            .get_many::<i64>("admin-critters")
+            .unwrap_or_default()
+            .copied()
+            .collect(),
+        admin_users: matches
+            .get_many::<u64>("admin-tg-ids")
+            .unwrap_or_default()
+            .copied()
+            .map(UserId)
+            .collect(),
     };
 
     // the bot has self healing properties built in, no need for retry!
-    tokio::spawn(bot::start_bot(state.clone()));
+    let webhook = matches
+        .get_one::<SocketAddr>("tg-webhook-listen")
+        .zip(matches.get_one::<Url>("tg-webhook-url"))
+        .map(|(addr, url)| {
+            let options = webhooks::Options::new(*addr, url.clone());
+            match matches.get_one::<String>("tg-webhook-secret") {
+                Some(secret) => options.secret_token(secret.clone()),
+                None => options,
+            }
+        });
+    tokio::spawn(bot::start_bot(state.clone(), webhook));
+    if matches.contains_id("templates") {
+        let templates = state.templates.clone();
+        tokio::spawn(async move { retry!(templates.watch()) });
+    }
+    if let Some(addr) = matches.get_one::<SocketAddr>("http-listen").copied() {
+        let state = state.clone();
+        tokio::spawn(async move { retry!(http::start_http(state.clone(), addr)) });
+    }
+    {
+        let state = state.clone();
+        tokio::spawn(async move { retry!(outbox::start_outbox(state.clone())) });
+    }
     retry!(events::start_event_processor(state.clone()));
 
     Ok(())
@@ -0,0 +1,83 @@
+use prometheus::{
+    Histogram, IntCounter, IntCounterVec, IntGauge, register_histogram, register_int_counter,
+    register_int_counter_vec, register_int_gauge,
+};
+use std::sync::LazyLock;
+
+pub static POLL_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "critter_poll_duration_seconds",
+        "Duration of a full sync with the crittersystem"
+    )
+    .unwrap()
+});
+
+/// Unix timestamp of the last poll which went through without errors
+pub static LAST_POLL: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "critter_last_successful_poll_timestamp_seconds",
+        "Unix timestamp of the last successful sync with the crittersystem"
+    )
+    .unwrap()
+});
+
+pub static API_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "critter_api_errors_total",
+        "Failed requests to the crittersystem by endpoint",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+pub static SHIFT_DIFFS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "critter_shift_diffs_total",
+        "Detected shift changes by kind",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static MESSAGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "critter_messages_total",
+        "Notifications handed to telegram by outcome",
+        &["status"]
+    )
+    .unwrap()
+});
+
+pub static BOT_BLOCKED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "critter_bot_blocked_total",
+        "Notifications which could not be delivered as the critter blocked the bot"
+    )
+    .unwrap()
+});
+
+pub static CACHE_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "critter_cache_lookups_total",
+        "Lookups of critter and chat ids by cache and result",
+        &["cache", "result"]
+    )
+    .unwrap()
+});
+
+/// Registers every metric up front, so they show up before their first use
+pub fn init() {
+    LazyLock::force(&POLL_DURATION);
+    LazyLock::force(&LAST_POLL);
+    LazyLock::force(&API_ERRORS);
+    LazyLock::force(&SHIFT_DIFFS);
+    LazyLock::force(&MESSAGES);
+    LazyLock::force(&BOT_BLOCKED);
+    LazyLock::force(&CACHE_LOOKUPS);
+}
+
+pub fn cache_lookup(cache: &str, hit: bool) {
+    CACHE_LOOKUPS
+        .with_label_values(&[cache, if hit { "hit" } else { "miss" }])
+        .inc();
+}
@@ -0,0 +1,109 @@
+use chrono::{TimeDelta, Utc};
+use color_eyre::eyre;
+use futures_util::future::join_all;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{error, trace, warn};
+
+use crate::{
+    State,
+    db::{OutboxEntry, OutboxStatus},
+    events::{Delivery, Recipient, handle_event},
+};
+
+const BATCH: i64 = 32;
+/// Time after which an event handed out is assumed lost, e.g. because of a crash mid delivery
+const LEASE: TimeDelta = TimeDelta::minutes(5);
+const MAX_ATTEMPTS: i32 = 8;
+
+/// Delivers the events persisted by the event processor, retrying failed deliveries with an exponential backoff
+#[tracing::instrument(name = "outbox", skip(state))]
+pub async fn start_outbox(state: State) -> eyre::Result<()> {
+    loop {
+        let entries = state.db.claim_outbox(BATCH, LEASE).await?;
+        if entries.is_empty() {
+            tokio::select! {
+                _ = sleep(Duration::from_secs(5)) => (),
+                _ = state.db.outbox_notified() => (),
+            }
+            continue;
+        }
+        // every entry belongs to another recipient, so they may go out in parallel.
+        // a failing entry must not cancel the others, their messages may be out already
+        join_all(entries.into_iter().map(|entry| async {
+            let key = entry.key.clone();
+            if let Err(err) = deliver(&state, entry).await {
+                error!(
+                    key,
+                    "Failed to deliver event, retrying once the lease runs out: {err}"
+                );
+            }
+        }))
+        .await;
+    }
+}
+
+async fn deliver(state: &State, entry: OutboxEntry) -> eyre::Result<()> {
+    let event = match entry.event {
+        Ok(event) => event,
+        Err(err) => {
+            error!(key = entry.key, "Malformed outbox event: {err}");
+            return state
+                .db
+                .finish_outbox(entry.id, OutboxStatus::Failed, Some(err.to_string()))
+                .await;
+        }
+    };
+    let (cid, lang) = match event.target() {
+        Recipient::Critter(uid) => match state.db.get_chat_id(uid).await? {
+            Some(cid) => (cid, state.db.critter_language(uid).await?),
+            None => {
+                return state
+                    .db
+                    .finish_outbox(entry.id, OutboxStatus::Skipped, None)
+                    .await;
+            }
+        },
+        Recipient::Chat(cid) => (cid, None),
+    };
+
+    match handle_event(state, &event, cid, lang.unwrap_or_default()).await {
+        Ok(Delivery::Sent) => {
+            trace!(key = entry.key, "delivered");
+            state
+                .db
+                .finish_outbox(entry.id, OutboxStatus::Sent, None)
+                .await
+        }
+        Ok(Delivery::Blocked) => {
+            if state.db.set_blocked(cid, true).await? {
+                warn!(
+                    key = entry.key,
+                    "Critter blocked the bot, holding back further messages"
+                );
+            }
+            state
+                .db
+                .finish_outbox(entry.id, OutboxStatus::Blocked, None)
+                .await
+        }
+        Err(err) if entry.attempts >= MAX_ATTEMPTS => {
+            error!(key = entry.key, "Giving up on delivering event: {err}");
+            state
+                .db
+                .finish_outbox(entry.id, OutboxStatus::Failed, Some(err.to_string()))
+                .await
+        }
+        Err(err) => {
+            let backoff = TimeDelta::seconds(5 << entry.attempts.min(7));
+            warn!(
+                key = entry.key,
+                "Failed to deliver event, retrying in {backoff}: {err}"
+            );
+            state
+                .db
+                .retry_outbox(entry.id, Utc::now() + backoff, err.to_string())
+                .await
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use chrono::{DateTime, Datelike, NaiveDate, TimeDelta, Utc};
+use chrono_tz::Tz;
+use color_eyre::eyre;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write},
+    sync::Arc,
+};
+use teloxide::{
+    types::ParseMode,
+    utils::html::{bold, escape},
+};
+
+use crate::{
+    db::CheckIn,
+    events::{Event, Shift},
+    i18n::{Lang, Text, fill},
+};
+
+/// Everything rendered in here is HTML and has to be sent with this parse mode
+pub const PARSE_MODE: ParseMode = ParseMode::Html;
+
+/// Human readable duration such as `1 h 20 min`, rounded to full minutes
+pub struct Span(pub TimeDelta, pub Lang);
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let t = self.1.text();
+        let minutes = (self.0.num_seconds().abs() + 30) / 60;
+        if minutes == 0 {
+            return write!(f, "{}", t.under_a_minute);
+        }
+        let parts = [
+            (minutes / 1440, t.days),
+            (minutes / 60 % 24, t.hours),
+            (minutes % 60, t.minutes),
+        ];
+        let mut first = true;
+        for (value, unit) in parts.into_iter().filter(|(value, _)| *value > 0) {
+            if !first {
+                write!(f, " ")?;
+            }
+            write!(f, "{value} {unit}")?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// Point in time relative to now, such as `in 1 h 20 min` or `5 min ago`
+pub struct Relative(pub DateTime<Utc>, pub Lang);
+
+impl Display for Relative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let t = self.1.text();
+        let delta = self.0.signed_duration_since(Utc::now());
+        let template = if delta < TimeDelta::zero() {
+            t.ago
+        } else {
+            t.from_now
+        };
+        write!(f, "{}", fill(template, &[("span", &Span(delta, self.1))]))
+    }
+}
+
+pub fn time(at: DateTime<Utc>, tz: Tz, lang: Lang) -> String {
+    let at = at.with_timezone(&tz);
+    format!("{} {}", date(at.date_naive(), lang), at.format("%H:%M"))
+}
+
+pub fn date(day: NaiveDate, lang: Lang) -> String {
+    let t = lang.text();
+    format!(
+        "{} {}",
+        t.weekdays[day.weekday().num_days_from_monday() as usize],
+        day.format(t.date)
+    )
+}
+
+/// Role the critter was assigned to the shift as
+pub fn role(shift: &Shift, uid: i64) -> &str {
+    shift
+        .critters
+        .iter()
+        .find(|c| c.2 == uid)
+        .map_or("?", |c| &c.1)
+}
+
+fn heading(text: &mut String, label: &str, shift: &Shift) -> std::fmt::Result {
+    writeln!(
+        text,
+        "{} {} ({})",
+        bold(&format!("{label}:")),
+        escape(&shift.title),
+        escape(&shift.r#type)
+    )
+}
+
+fn shift_as(t: &Text, shift: &Shift, role: &str) -> String {
+    fill(
+        t.shift_as,
+        &[
+            ("title", &escape(&shift.title)),
+            ("type", &escape(&shift.r#type)),
+            ("role", &escape(role)),
+        ],
+    )
+}
+
+fn heading_as(
+    text: &mut String,
+    t: &Text,
+    label: &str,
+    shift: &Shift,
+    role: &str,
+) -> std::fmt::Result {
+    writeln!(
+        text,
+        "{} {}",
+        bold(&format!("{label}:")),
+        shift_as(t, shift, role)
+    )
+}
+
+fn details(text: &mut String, lang: Lang, shift: &Shift) -> std::fmt::Result {
+    let t = lang.text();
+    writeln!(text, "{}: {}", t.location, escape(&shift.location))?;
+    writeln!(
+        text,
+        "{}: {} ({})",
+        t.starts,
+        time(shift.start, shift.tz, lang),
+        Relative(shift.start, lang)
+    )?;
+    writeln!(
+        text,
+        "{}: {} ({})",
+        t.ends,
+        time(shift.end, shift.tz, lang),
+        total(shift, lang)
+    )?;
+    if shift.ppe {
+        writeln!(text, "{}", bold(t.ppe))?;
+    }
+    Ok(())
+}
+
+pub fn total(shift: &Shift, lang: Lang) -> String {
+    fill(
+        lang.text().total,
+        &[(
+            "span",
+            &Span(shift.end.signed_duration_since(shift.start), lang),
+        )],
+    )
+}
+
+fn staffing(text: &mut String, t: &Text, shift: &Shift) -> std::fmt::Result {
+    writeln!(
+        text,
+        "{}: ({}/{})",
+        t.assigned_critters,
+        shift.critters.len(),
+        shift.req
+    )
+}
+
+/// Message sent out for an event
+pub fn event(event: &Event, lang: Lang) -> eyre::Result<String> {
+    let t = lang.text();
+    let mut text = String::new();
+    match event {
+        Event::UserUpcoming { shift, uid, .. } => {
+            heading_as(&mut text, t, t.upcoming, shift, role(shift, *uid))?;
+            details(&mut text, lang, shift)?;
+        }
+        Event::ManagerUpcoming { shift, .. } => {
+            heading(&mut text, t.upcoming, shift)?;
+            details(&mut text, lang, shift)?;
+            staffing(&mut text, t, shift)?;
+            writeln!(text, "{}", roster(shift, lang))?;
+            writeln!(text, "{}", fill(t.roster_hint, &[("id", &shift.id)]))?;
+        }
+        Event::UserDaily { next, uid } => {
+            writeln!(text, "{}", t.today)?;
+            writeln!(text, "{}", digest(next, *uid, lang))?;
+        }
+        Event::UserTimeChanged {
+            uid,
+            shift,
+            old_start,
+            old_end,
+            ..
+        } => {
+            heading_as(&mut text, t, t.time_changed, shift, role(shift, *uid))?;
+            writeln!(
+                text,
+                "{}: {}, {}",
+                t.now_starts,
+                bold(&format!(
+                    "{} ({})",
+                    time(shift.start, shift.tz, lang),
+                    Relative(shift.start, lang)
+                )),
+                fill(t.originally, &[("time", &time(*old_start, shift.tz, lang))]),
+            )?;
+            writeln!(
+                text,
+                "{}: {}, {}",
+                t.ends,
+                bold(&format!(
+                    "{} ({})",
+                    time(shift.end, shift.tz, lang),
+                    total(shift, lang)
+                )),
+                fill(t.originally, &[("time", &time(*old_end, shift.tz, lang))]),
+            )?;
+        }
+        Event::UserCanceled { uid, shift, .. } => {
+            heading_as(&mut text, t, t.canceled, shift, role(shift, *uid))?;
+            writeln!(text, "{}", t.no_show)?;
+        }
+        Event::UserAssigned { uid, shift, .. } => {
+            heading_as(&mut text, t, t.assigned, shift, role(shift, *uid))?;
+            details(&mut text, lang, shift)?;
+        }
+        Event::UserUnassigned { role, shift, .. } => {
+            heading_as(&mut text, t, t.unassigned, shift, role)?;
+            writeln!(text, "{}: {}", t.starts, time(shift.start, shift.tz, lang))?;
+            writeln!(text, "{}", t.no_show)?;
+        }
+        Event::Understaffed { shift, .. } => {
+            heading(&mut text, t.understaffed, shift)?;
+            writeln!(text, "{}: {}", t.location, escape(&shift.location))?;
+            writeln!(
+                text,
+                "{}: {} ({})",
+                t.starts,
+                time(shift.start, shift.tz, lang),
+                Relative(shift.start, lang)
+            )?;
+            staffing(&mut text, t, shift)?;
+        }
+        Event::Staffed { shift, .. } => {
+            heading(&mut text, t.staffed, shift)?;
+            staffing(&mut text, t, shift)?;
+        }
+        Event::Broadcast { text: message, .. } => {
+            writeln!(text, "{}", bold(&format!("{}:", t.broadcast)))?;
+            writeln!(text, "{}", escape(message))?;
+        }
+    }
+    Ok(text)
+}
+
+fn member(t: &Text, (name, r#type, _, staff): &(Arc<str>, Arc<str>, i64, bool)) -> String {
+    let name = match staff {
+        true => format!("{} ({})", escape(name), t.staff),
+        false => escape(name),
+    };
+    fill(t.member_as, &[("name", &name), ("role", &escape(r#type))])
+}
+
+/// Every critter of the shift along with their role, one per line
+pub fn roster(shift: &Shift, lang: Lang) -> String {
+    let t = lang.text();
+    shift
+        .critters
+        .iter()
+        .map(|critter| format!("- {}", member(t, critter)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn check_in(status: CheckIn, lang: Lang) -> &'static str {
+    let t = lang.text();
+    match status {
+        CheckIn::OnMyWay => t.on_my_way,
+        CheckIn::CheckedIn => t.checked_in,
+        CheckIn::CantMakeIt => t.cant_make_it,
+    }
+}
+
+/// Roster of a shift for its managers along with the latest answer of every critter to their reminder
+pub fn check_ins(
+    shift: &Shift,
+    answers: &HashMap<i64, (CheckIn, DateTime<Utc>)>,
+    lang: Lang,
+) -> eyre::Result<String> {
+    let t = lang.text();
+    let mut text = String::new();
+    heading(&mut text, t.check_ins, shift)?;
+    writeln!(
+        text,
+        "{}: {} ({})",
+        t.starts,
+        time(shift.start, shift.tz, lang),
+        Relative(shift.start, lang)
+    )?;
+    staffing(&mut text, t, shift)?;
+    for critter in &shift.critters {
+        let answer = match answers.get(&critter.2) {
+            Some((status, at)) => format!(
+                "{} ({})",
+                bold(check_in(*status, lang)),
+                Relative(*at, lang)
+            ),
+            None => t.no_answer.into(),
+        };
+        writeln!(text, "- {}: {answer}", member(t, critter))?;
+    }
+    Ok(text)
+}
+
+/// Summary of the given shifts of a critter, one per line
+pub fn digest(shifts: &[Shift], uid: i64, lang: Lang) -> String {
+    shifts
+        .iter()
+        .map(|shift| ShiftLine { shift, uid, lang }.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Post of an open shift, kept free of relative times so it only changes along with the shift
+pub fn open_shift(shift: &Shift, status: &str, lang: Lang) -> eyre::Result<String> {
+    let t = lang.text();
+    let mut text = String::new();
+    heading(&mut text, status, shift)?;
+    writeln!(text, "{}: {}", t.location, escape(&shift.location))?;
+    writeln!(text, "{}: {}", t.starts, time(shift.start, shift.tz, lang))?;
+    writeln!(text, "{}: {}", t.ends, time(shift.end, shift.tz, lang))?;
+    writeln!(
+        text,
+        "{}: {}/{}",
+        t.free_spots,
+        shift.req.saturating_sub(shift.critters.len()),
+        shift.req
+    )?;
+    if shift.ppe {
+        writeln!(text, "{}", bold(t.ppe))?;
+    }
+    Ok(text)
+}
+
+/// Single line summary of a shift from the perspective of one of its critters
+pub struct ShiftLine<'a> {
+    pub shift: &'a Shift,
+    pub uid: i64,
+    pub lang: Lang,
+}
+
+impl Display for ShiftLine<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ShiftLine { shift, uid, lang } = *self;
+        let t = lang.text();
+        write!(
+            f,
+            "- {} @ {}: {} ({}) -> {} ({}){}",
+            bold(&shift_as(t, shift, role(shift, uid))),
+            escape(&shift.location),
+            time(shift.start, shift.tz, lang),
+            Relative(shift.start, lang),
+            time(shift.end, shift.tz, lang),
+            total(shift, lang),
+            match shift.ppe {
+                true => format!(" {}", bold(&format!("[{}]", t.ppe_short))),
+                false => String::new(),
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(seconds: i64) -> String {
+        Span(TimeDelta::seconds(seconds), Lang::En).to_string()
+    }
+
+    #[test]
+    fn span_rounds_to_minutes() {
+        assert_eq!(span(0), "less than a minute");
+        assert_eq!(span(29), "less than a minute");
+        assert_eq!(span(30), "1 min");
+        assert_eq!(span(89 * 60 + 40), "1 h 30 min");
+        assert_eq!(span(-(2 * 3600 + 10)), "2 h");
+        assert_eq!(span(26 * 3600 + 5 * 60), "1 d 2 h 5 min");
+    }
+
+    #[test]
+    fn relative_to_now() {
+        let at = |seconds| Utc::now() + TimeDelta::seconds(seconds);
+        assert_eq!(
+            Relative(at(90 * 60 + 10), Lang::En).to_string(),
+            "in 1 h 30 min"
+        );
+        assert_eq!(Relative(at(-5 * 60), Lang::En).to_string(), "5 min ago");
+        assert_eq!(Relative(at(-5 * 60), Lang::De).to_string(), "vor 5 Min");
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use teloxide::{RequestError, types::ChatId};
+use tokio::time::{Instant, sleep};
+use tracing::warn;
+
+/// Telegram allows about 30 messages per second overall
+const GLOBAL_RATE: f64 = 30.0;
+/// ... one message per second to the same private chat
+const CHAT_INTERVAL: Duration = Duration::from_secs(1);
+/// ... and 20 messages per minute to the same group
+const GROUP_INTERVAL: Duration = Duration::from_secs(3);
+const NETWORK_RETRIES: u32 = 3;
+
+/// Token bucket shared by everything sending out messages in bulk, keeping us within telegrams rate limits
+#[derive(Clone)]
+pub struct Sender {
+    limits: Arc<Mutex<Limits>>,
+}
+
+struct Limits {
+    tokens: f64,
+    refilled: Instant,
+    /// Set whenever telegram asks us to back off
+    paused_until: Option<Instant>,
+    /// Earliest time the next message may be sent to a chat
+    chats: HashMap<ChatId, Instant>,
+}
+
+impl Default for Sender {
+    fn default() -> Self {
+        Self {
+            limits: Arc::new(Mutex::new(Limits {
+                tokens: GLOBAL_RATE,
+                refilled: Instant::now(),
+                paused_until: None,
+                chats: HashMap::new(),
+            })),
+        }
+    }
+}
+
+impl Sender {
+    /// Runs a request towards a chat once the rate limits allow for it.
+    /// Flood waits are honoured and network failures retried, every other error is handed back.
+    pub async fn request<T, F, Fut>(&self, cid: ChatId, req: F) -> Result<T, RequestError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, RequestError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            self.acquire(cid).await;
+            match req().await {
+                Err(RequestError::RetryAfter(secs)) => {
+                    warn!("Telegram asked us to back off for {}s", secs.seconds());
+                    self.pause(secs.duration());
+                }
+                Err(RequestError::Network(err)) if attempt < NETWORK_RETRIES => {
+                    attempt += 1;
+                    warn!("Network error talking to telegram, retry {attempt}: {err}");
+                    sleep(Duration::from_secs(1 << attempt)).await;
+                }
+                Err(RequestError::Io(err)) if attempt < NETWORK_RETRIES => {
+                    attempt += 1;
+                    warn!("IO error talking to telegram, retry {attempt}: {err}");
+                    sleep(Duration::from_secs(1 << attempt)).await;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn acquire(&self, cid: ChatId) {
+        loop {
+            let wait = {
+                let mut limits = self.limits.lock().unwrap();
+                let now = Instant::now();
+
+                let elapsed = now.duration_since(limits.refilled).as_secs_f64();
+                limits.tokens = (limits.tokens + elapsed * GLOBAL_RATE).min(GLOBAL_RATE);
+                limits.refilled = now;
+
+                let paused = limits
+                    .paused_until
+                    .map(|until| until.saturating_duration_since(now));
+                let chat = limits
+                    .chats
+                    .get(&cid)
+                    .map(|next| next.saturating_duration_since(now));
+                let global = (limits.tokens < 1.0)
+                    .then(|| Duration::from_secs_f64((1.0 - limits.tokens) / GLOBAL_RATE));
+                let wait = [paused, chat, global].into_iter().flatten().max();
+
+                match wait {
+                    Some(wait) if !wait.is_zero() => wait,
+                    _ => {
+                        limits.tokens -= 1.0;
+                        let interval = if cid.is_group() || cid.is_channel_or_supergroup() {
+                            GROUP_INTERVAL
+                        } else {
+                            CHAT_INTERVAL
+                        };
+                        limits.chats.insert(cid, now + interval);
+                        if limits.chats.len() > 1024 {
+                            limits.chats.retain(|_, next| *next > now);
+                        }
+                        return;
+                    }
+                }
+            };
+            sleep(wait).await;
+        }
+    }
+
+    fn pause(&self, duration: Duration) {
+        let mut limits = self.limits.lock().unwrap();
+        let until = Instant::now() + duration;
+        limits.paused_until = Some(limits.paused_until.map_or(until, |cur| cur.max(until)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::Seconds;
+
+    const CHAT: ChatId = ChatId(1);
+    const GROUP: ChatId = ChatId(-1);
+
+    async fn elapsed(f: impl Future<Output = ()>) -> Duration {
+        let start = Instant::now();
+        f.await;
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spaces_messages_to_the_same_chat() {
+        let sender = Sender::default();
+        sender.acquire(CHAT).await;
+        assert_eq!(elapsed(sender.acquire(CHAT)).await, CHAT_INTERVAL);
+        sender.acquire(GROUP).await;
+        assert_eq!(elapsed(sender.acquire(GROUP)).await, GROUP_INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn different_chats_share_the_global_rate() {
+        let sender = Sender::default();
+        let burst = elapsed(async {
+            for chat in 0..GLOBAL_RATE as i64 {
+                sender.acquire(ChatId(chat + 100)).await;
+            }
+        })
+        .await;
+        assert!(burst.is_zero());
+        let next = elapsed(sender.acquire(ChatId(99))).await;
+        // timers only have millisecond precision
+        let token = Duration::from_secs_f64(1.0 / GLOBAL_RATE);
+        assert!(next >= token && next <= token + Duration::from_millis(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn honours_retry_after() {
+        let sender = Sender::default();
+        let calls = Mutex::new(0);
+        let start = Instant::now();
+        let res = sender
+            .request(CHAT, || async {
+                let mut calls = calls.lock().unwrap();
+                *calls += 1;
+                match *calls {
+                    1 => Err(RequestError::RetryAfter(Seconds::from_seconds(5))),
+                    _ => Ok(()),
+                }
+            })
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(*calls.lock().unwrap(), 2);
+        assert!(start.elapsed() >= Duration::from_secs(5));
+    }
+}
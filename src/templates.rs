@@ -0,0 +1,359 @@
+use color_eyre::eyre::{self, WrapErr, bail};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use teloxide::utils::html::{bold, escape};
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{error, info};
+
+use crate::{
+    events::{Event, Shift},
+    i18n::{Lang, fill},
+    render::{self, Relative, Span},
+};
+
+/// Variables available to the templates of every event about a single shift
+const SHIFT_VARS: &[&str] = &[
+    "id",
+    "title",
+    "type",
+    "location",
+    "start",
+    "end",
+    "starts_in",
+    "duration",
+    "ppe",
+    "assigned",
+    "required",
+];
+
+/// Event kinds which can be templated along with the variables they get on top of [`SHIFT_VARS`]
+const KINDS: &[(&str, &[&str])] = &[
+    ("user_upcoming", &["role"]),
+    ("user_time_changed", &["role", "old_start", "old_end"]),
+    ("user_canceled", &["role"]),
+    ("user_assigned", &["role"]),
+    ("user_unassigned", &["role"]),
+    ("manager_upcoming", &["roster"]),
+    ("understaffed", &[]),
+    ("staffed", &[]),
+];
+
+/// The daily digest covers multiple shifts, so it only gets the rendered list of them
+const DAILY: (&str, &[&str]) = ("user_daily", &["shifts"]);
+
+/// Tags telegram accepts in HTML messages
+const TAGS: &[&str] = &[
+    "b",
+    "strong",
+    "i",
+    "em",
+    "u",
+    "ins",
+    "s",
+    "strike",
+    "del",
+    "span",
+    "tg-spoiler",
+    "a",
+    "tg-emoji",
+    "code",
+    "pre",
+    "blockquote",
+];
+
+type Loaded = HashMap<(&'static str, Option<Lang>), Arc<str>>;
+
+/// Message templates provided by the operators, replacing the built in messages of the events they cover.
+///
+/// Every `<kind>.html` file in the directory is used for all languages, `<kind>.<lang>.html` only for the given one.
+/// Templates are HTML and reference variables as `{name}`.
+#[derive(Clone, Default)]
+pub struct Templates {
+    dir: Option<Arc<Path>>,
+    loaded: Arc<RwLock<Loaded>>,
+}
+
+impl Templates {
+    /// Loads every template in the directory, failing on templates which could not be rendered
+    pub fn load(dir: Option<PathBuf>) -> eyre::Result<Self> {
+        let loaded = match &dir {
+            Some(dir) => read(dir)?,
+            None => Loaded::new(),
+        };
+        Ok(Self {
+            dir: dir.map(Arc::from),
+            loaded: Arc::new(RwLock::new(loaded)),
+        })
+    }
+
+    /// Rereads the directory, the previous templates are kept if any of them is invalid
+    pub fn reload(&self) -> eyre::Result<usize> {
+        let Some(dir) = &self.dir else {
+            return Ok(0);
+        };
+        let loaded = read(dir)?;
+        let count = loaded.len();
+        *self.loaded.write().unwrap() = loaded;
+        Ok(count)
+    }
+
+    /// Renders an event through its template, `None` if there is none for it
+    pub fn render(&self, event: &Event, lang: Lang) -> Option<String> {
+        let template = {
+            let loaded = self.loaded.read().unwrap();
+            loaded
+                .get(&(event.kind(), Some(lang)))
+                .or_else(|| loaded.get(&(event.kind(), None)))?
+                .clone()
+        };
+        let vars = vars(event, lang);
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (*name, value as &dyn Display))
+            .collect::<Vec<_>>();
+        Some(fill(&template, &vars))
+    }
+
+    /// Reloads the templates whenever we receive a SIGHUP
+    pub async fn watch(&self) -> eyre::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        while hangup.recv().await.is_some() {
+            match self.reload() {
+                Ok(count) => info!("Reloaded {count} message templates"),
+                Err(err) => {
+                    error!("Failed to reload message templates, keeping the old ones: {err:?}")
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read(dir: &Path) -> eyre::Result<Loaded> {
+    let mut loaded = Loaded::new();
+    for entry in fs::read_dir(dir).wrap_err_with(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some(stem) = name.strip_suffix(".html") else {
+            continue;
+        };
+        let (kind, lang) = match stem.split_once('.') {
+            Some((kind, code)) => match Lang::from_code(code) {
+                Some(lang) if lang.code() == code => (kind, Some(lang)),
+                _ => bail!("{}: unknown language `{code}`", path.display()),
+            },
+            None => (stem, None),
+        };
+        let Some((kind, vars)) = KINDS.iter().chain([&DAILY]).find(|(name, _)| *name == kind)
+        else {
+            bail!("{}: unknown event `{kind}`", path.display());
+        };
+
+        let template =
+            fs::read_to_string(&path).wrap_err_with(|| format!("reading {}", path.display()))?;
+        for var in placeholders(&template) {
+            let shift = *kind != DAILY.0 && SHIFT_VARS.contains(&var);
+            if !shift && !vars.contains(&var) {
+                bail!("{}: unknown variable `{{{var}}}`", path.display());
+            }
+        }
+        // telegram rejects the whole message otherwise, which we would only notice once it is sent
+        if let Err(err) = check_html(&template) {
+            bail!("{}: {err}", path.display());
+        }
+        loaded.insert((kind, lang), template.trim_end().into());
+    }
+    Ok(loaded)
+}
+
+/// Names of every `{name}` placeholder in a template
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|part| {
+        let (name, _) = part.split_once('}')?;
+        (!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+            .then_some(name)
+    })
+}
+
+/// Makes sure telegram accepts the template as HTML: only supported tags, properly nested and no stray `<` or `&`
+fn check_html(template: &str) -> Result<(), String> {
+    let mut open = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['<', '&']) {
+        let tail = &rest[start..];
+        if let Some(entity) = tail.strip_prefix('&') {
+            let entity = entity.split_once(';').map(|(entity, _)| entity);
+            let known = entity.is_some_and(|entity| {
+                matches!(entity, "lt" | "gt" | "amp" | "quot")
+                    || entity.strip_prefix("#x").map_or_else(
+                        || {
+                            entity
+                                .strip_prefix('#')
+                                .is_some_and(|n| n.parse::<u32>().is_ok())
+                        },
+                        |n| u32::from_str_radix(n, 16).is_ok(),
+                    )
+            });
+            if !known {
+                return Err("stray `&`, write `&amp;` instead".into());
+            }
+            rest = &tail[entity.unwrap_or_default().len() + 2..];
+            continue;
+        }
+        let Some(end) = tail.find('>') else {
+            return Err("unclosed `<`, write `&lt;` instead".into());
+        };
+        let tag = &tail[1..end];
+        rest = &tail[end + 1..];
+        match tag.strip_prefix('/') {
+            Some(name) => {
+                let name = name.trim();
+                if open.pop() != Some(name) {
+                    return Err(format!("unexpected `</{name}>`"));
+                }
+            }
+            None => {
+                let name = tag.split_whitespace().next().unwrap_or_default();
+                if !TAGS.contains(&name) {
+                    return Err(format!("unsupported tag `<{name}>`"));
+                }
+                open.push(name);
+            }
+        }
+    }
+    match open.pop() {
+        Some(name) => Err(format!("`<{name}>` is never closed")),
+        None => Ok(()),
+    }
+}
+
+/// Values of the variables of an event, already escaped
+fn vars(event: &Event, lang: Lang) -> Vec<(&'static str, String)> {
+    let shift = match event {
+        Event::UserDaily { next, uid } => {
+            return vec![("shifts", render::digest(next, *uid, lang))];
+        }
+        // broadcasts are written by the admins themselves
+        Event::Broadcast { .. } => return Vec::new(),
+        Event::UserUpcoming { shift, .. }
+        | Event::UserTimeChanged { shift, .. }
+        | Event::UserCanceled { shift, .. }
+        | Event::UserAssigned { shift, .. }
+        | Event::UserUnassigned { shift, .. }
+        | Event::ManagerUpcoming { shift, .. }
+        | Event::Understaffed { shift, .. }
+        | Event::Staffed { shift, .. } => shift,
+    };
+    let mut vars = shift_vars(shift, lang);
+    match event {
+        Event::UserUpcoming { uid, .. }
+        | Event::UserCanceled { uid, .. }
+        | Event::UserAssigned { uid, .. } => {
+            vars.push(("role", escape(render::role(shift, *uid))));
+        }
+        Event::UserUnassigned { role, .. } => vars.push(("role", escape(role))),
+        Event::UserTimeChanged {
+            uid,
+            old_start,
+            old_end,
+            ..
+        } => {
+            vars.push(("role", escape(render::role(shift, *uid))));
+            vars.push(("old_start", render::time(*old_start, shift.tz, lang)));
+            vars.push(("old_end", render::time(*old_end, shift.tz, lang)));
+        }
+        Event::ManagerUpcoming { .. } => vars.push(("roster", render::roster(shift, lang))),
+        _ => {}
+    }
+    vars
+}
+
+fn shift_vars(shift: &Shift, lang: Lang) -> Vec<(&'static str, String)> {
+    vec![
+        ("id", shift.id.to_string()),
+        ("title", escape(&shift.title)),
+        ("type", escape(&shift.r#type)),
+        ("location", escape(&shift.location)),
+        ("start", render::time(shift.start, shift.tz, lang)),
+        ("end", render::time(shift.end, shift.tz, lang)),
+        ("starts_in", Relative(shift.start, lang).to_string()),
+        (
+            "duration",
+            Span(shift.end.signed_duration_since(shift.start), lang).to_string(),
+        ),
+        (
+            "ppe",
+            match shift.ppe {
+                true => bold(lang.text().ppe),
+                false => String::new(),
+            },
+        ),
+        ("assigned", shift.critters.len().to_string()),
+        ("required", shift.req.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_telegram_html() {
+        assert!(
+            check_html("<b>{title}</b> &amp; <a href=\"https://x.org/?a=1&amp;b=2\">link</a>")
+                .is_ok()
+        );
+        assert!(
+            check_html("<blockquote><i>nested</i></blockquote> &lt;3 &#128512; &#x1F600;").is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_broken_html() {
+        assert!(check_html("<b>unclosed").is_err());
+        assert!(check_html("<b><i>crossed</b></i>").is_err());
+        assert!(check_html("line<br>break").is_err());
+        assert!(check_html("</b>").is_err());
+        assert!(check_html("1 < 2").is_err());
+        assert!(check_html("fish & chips").is_err());
+    }
+
+    #[test]
+    fn finds_placeholders() {
+        let found = placeholders("{title} {} {a b} {{role}} {old_start").collect::<Vec<_>>();
+        assert_eq!(found, ["title", "role"]);
+    }
+
+    /// Loads a directory holding just the given template
+    fn load(name: &str, template: &str) -> eyre::Result<Loaded> {
+        let dir =
+            std::env::temp_dir().join(format!("critter-templates-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(name), template)?;
+        let loaded = read(&dir);
+        fs::remove_dir_all(&dir)?;
+        loaded
+    }
+
+    #[test]
+    fn checks_templates() {
+        let loaded = load("user_upcoming.de.html", "<b>{title}</b> als {role}\n").unwrap();
+        assert_eq!(
+            &*loaded[&("user_upcoming", Some(Lang::De))],
+            "<b>{title}</b> als {role}"
+        );
+        assert!(load("user_daily.html", "{shifts}").is_ok());
+
+        assert!(load("staffed.html", "{role}").is_err());
+        assert!(load("user_daily.html", "{title}").is_err());
+        assert!(load("user_upcoming.fr.html", "{title}").is_err());
+        assert!(load("bogus.html", "{title}").is_err());
+    }
+}